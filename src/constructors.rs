@@ -3,107 +3,133 @@
 //! These function are used to convert R objects into geo-types geometry.
 //! These functions mimic the structure of sfg objects from the sf package.
 //! Additional quality of life constructors are made available in {rsgeo}.
-use crate::Geom;
+//!
+//! Constructors return an [`SfConversionError`] when the R object does not
+//! have the structure of the corresponding sfg object.
+//!
+//! Geometries can also be built from long format coordinate data, such as a
+//! data.frame of GPS points with `x`, `y`, and id columns, in the style of
+//! sfheaders e.g. [`linestrings_from_long()`] and [`polygons_from_long()`].
+//! The resulting geometries can be passed to [`to_sfc()`](crate::tosf::to_sfc).
+use crate::{error::SfConversionError, fromsf::sfg_to_geom, geometry_name, Geom};
 use extendr_api::prelude::*;
+use extendr_api::Rtype;
 use geo_types::{
-    coord, point, Coord, LineString, MultiLineString, MultiPoint, MultiPolygon, Point, Polygon,
+    coord, point, Coord, Geometry, GeometryCollection, Line, LineString, MultiLineString,
+    MultiPoint, MultiPolygon, Point, Polygon, Rect, Triangle,
 };
+use std::ops::Range;
+use std::result::Result;
 
 // TODO REMOVE SCALAR CLASSES
 /// Create a single `point` from an x and y value.
 pub fn geom_point(x: f64, y: f64) -> Robj {
-    Geom::from(Point::new(x, y))
-        .into_robj()
-        .set_class(["point", "Geom"])
-        .unwrap()
-        .clone()
+    into_geom_robj(Point::new(x, y).into())
 }
 
 /// Create a single `multipoint` from a 2 dimensional matrix.
-pub fn geom_multipoint(x: RArray<f64, [usize; 2]>) -> Robj {
-    let mpnt = MultiPoint::new(matrix_to_points(x));
-    Geom::from(mpnt)
-        .into_robj()
-        .set_class(["multipoint", "Geom"])
-        .unwrap()
-        .clone()
+pub fn geom_multipoint(x: RArray<f64, [usize; 2]>) -> Result<Robj, SfConversionError> {
+    let mpnt = MultiPoint::new(matrix_to_points(x)?);
+    Ok(into_geom_robj(mpnt.into()))
 }
 
 /// Create a single `linestring` from a 2 dimensional matrix.
-pub fn geom_linestring(x: RArray<f64, [usize; 2]>) -> Robj {
-    let coords = matrix_to_coords(x);
-    let lns = LineString::new(coords);
-    Geom::from(lns)
-        .into_robj()
-        .set_class(["linestring", "Geom"])
-        .unwrap()
-        .clone()
+pub fn geom_linestring(x: RArray<f64, [usize; 2]>) -> Result<Robj, SfConversionError> {
+    let lns = LineString::new(matrix_to_coords(x)?);
+    Ok(into_geom_robj(lns.into()))
 }
 
 /// Create a single `multilinestring` from a list of 2 dimensional matrices.
-pub fn geom_multilinestring(x: List) -> Robj {
-    let vec_lns = x
-        .into_iter()
-        .map(|(_, x)| LineString::new(matrix_to_coords(RMatrix::try_from(x).unwrap())))
-        .collect::<Vec<LineString>>();
-
-    Geom::from(MultiLineString::new(vec_lns))
-        .into_robj()
-        .set_class(["multilinestring", "Geom"])
-        .unwrap()
-        .clone()
+pub fn geom_multilinestring(x: List) -> Result<Robj, SfConversionError> {
+    Ok(into_geom_robj(multilinestring_inner(x)?.into()))
 }
 
 /// Create a single `polygon` from a list of 2 dimensional matrices.
-pub fn geom_polygon(x: List) -> Robj {
-    let n = x.len();
+/// An empty list creates an empty polygon.
+pub fn geom_polygon(x: List) -> Result<Robj, SfConversionError> {
+    Ok(into_geom_robj(polygon_inner(x)?.into()))
+}
 
-    let mut linestrings: Vec<LineString> = Vec::with_capacity(n);
+/// Create a single `multipolygon` from a list of lists of 2 dimensional matrices.
+pub fn geom_multipolygon(x: List) -> Result<Robj, SfConversionError> {
+    Ok(into_geom_robj(multipolygon_inner(x)?.into()))
+}
 
-    let exterior = matrix_to_coords(x[0].as_matrix().unwrap());
-    let exterior = LineString::new(exterior);
+/// Create a single `geometrycollection` from a list of sfg objects.
+/// Nested geometry collections are converted recursively.
+pub fn geom_geometrycollection(x: List) -> Result<Robj, SfConversionError> {
+    Ok(into_geom_robj(geometrycollection_inner(x)?.into()))
+}
 
-    if n > 1 {
-        for i in 1..n {
-            let xi: RMatrix<f64> = x[i].to_owned().try_into().unwrap();
-            let coords = matrix_to_coords(xi);
-            let line = LineString::new(coords);
-            linestrings.push(line);
-        }
+/// Create a single `line` from a 2 dimensional matrix with 2 rows: the start
+/// and end coordinates.
+pub fn geom_line(x: RArray<f64, [usize; 2]>) -> Result<Robj, SfConversionError> {
+    let coords = matrix_to_coords(x)?;
+    if coords.len() != 2 {
+        return Err(SfConversionError::LengthMismatch {
+            expected: 2,
+            found: coords.len(),
+        });
     }
 
-    let polygon = Polygon::new(exterior, linestrings);
+    Ok(into_geom_robj(Line::new(coords[0], coords[1]).into()))
+}
 
-    Geom::from(polygon)
-        .into_robj()
-        .set_class(["polygon", "Geom"])
-        .unwrap()
-        .clone()
+/// Create a single `rect` from a bounding box vector in the order
+/// `xmin`, `ymin`, `xmax`, `ymax` such as the result of `sf::st_bbox()`.
+pub fn geom_rect(x: Doubles) -> Result<Robj, SfConversionError> {
+    Ok(into_geom_robj(bbox_to_rect(x)?.into()))
 }
 
-/// Create a single `multipolygon` from a list of lists of 2 dimensional matrices.
-pub fn geom_multipolygon(x: List) -> Robj {
-    let res = MultiPolygon::new(
-        x.into_iter()
-            .map(|(_, x)| polygon_inner(List::try_from(x).unwrap()))
-            .collect::<Vec<Polygon>>(),
-    );
-
-    Geom::from(res)
-        .into_robj()
-        .set_class(["multipolygon", "Geom"])
-        .unwrap()
-        .clone()
+/// Create a single `triangle` from a 2 dimensional matrix with 3 rows. A closed
+/// matrix of 4 rows, where the first and last coordinates are identical, is
+/// also accepted.
+pub fn geom_triangle(x: RArray<f64, [usize; 2]>) -> Result<Robj, SfConversionError> {
+    let mut coords = matrix_to_coords(x)?;
+    if coords.len() == 4 && coords[0] == coords[3] {
+        coords.pop();
+    }
+
+    if coords.len() != 3 {
+        return Err(SfConversionError::LengthMismatch {
+            expected: 3,
+            found: coords.len(),
+        });
+    }
+
+    let tri = Triangle::new(coords[0], coords[1], coords[2]);
+    Ok(into_geom_robj(tri.into()))
+}
+
+/// Convert a bounding box vector in the order `xmin`, `ymin`, `xmax`, `ymax`
+/// into a `Rect`.
+pub fn bbox_to_rect(x: Doubles) -> Result<Rect, SfConversionError> {
+    if x.len() != 4 {
+        return Err(SfConversionError::LengthMismatch {
+            expected: 4,
+            found: x.len(),
+        });
+    }
+
+    Ok(Rect::new(
+        coord! { x: x[0].inner(), y: x[1].inner() },
+        coord! { x: x[2].inner(), y: x[3].inner() },
+    ))
 }
 
 // First, I need to take a matrix and convert into coordinates
-/// Convert an `RMatrix<f64>` into a vector of `Coords`.
-pub fn matrix_to_coords(x: RMatrix<f64>) -> Vec<Coord> {
+/// Convert an `RMatrix<f64>` into a vector of `Coords`. Only `XY` matrices are
+/// supported. Use [`sfg_to_geom_zm()`](crate::fromsf::sfg_to_geom_zm) for geometries
+/// with Z or M dimensions.
+pub fn matrix_to_coords(x: RMatrix<f64>) -> Result<Vec<Coord>, SfConversionError> {
     let nrow = x.nrows();
     let ncol = x.ncols();
 
     if ncol != 2 {
-        panic!("Matrix should have only 2 columns for x and y coordinates, respectively.")
+        return Err(SfConversionError::WrongDimensions {
+            expected: 2,
+            found: ncol,
+        });
     }
 
     //let n = nrow.clone();
@@ -113,17 +139,20 @@ pub fn matrix_to_coords(x: RMatrix<f64>) -> Vec<Coord> {
         let crd = coord! {x: x[[i, 0]], y: x[[i, 1]]};
         coords.push(crd);
     }
-    coords
+    Ok(coords)
 }
 
 /// Convert an `RMatrix<f64>` into a vector of `Points`. Is
 /// used internally to create `MultiPoint`s.
-pub fn matrix_to_points(x: RMatrix<f64>) -> Vec<Point> {
+pub fn matrix_to_points(x: RMatrix<f64>) -> Result<Vec<Point>, SfConversionError> {
     let nrow = x.nrows();
     let ncol = x.ncols();
 
     if ncol != 2 {
-        panic!("Matrix should have only 2 columns for x and y coordinates, respectively.")
+        return Err(SfConversionError::WrongDimensions {
+            expected: 2,
+            found: ncol,
+        });
     }
 
     //let n = nrow.clone();
@@ -133,26 +162,312 @@ pub fn matrix_to_points(x: RMatrix<f64>) -> Vec<Point> {
         let crd = point! {x: x[[i, 0]], y: x[[i, 1]]};
         coords.push(crd);
     }
-    coords
+    Ok(coords)
+}
+
+// Creates a `Geom` pointer with the lower case geometry type and `Geom` class
+pub(crate) fn into_geom_robj(x: Geometry) -> Robj {
+    let cls = geometry_name(&x).to_lowercase();
+    Geom::from(x)
+        .into_robj()
+        .set_class([cls.as_str(), "Geom"])
+        .unwrap()
+        .clone()
+}
+
+// The functions below check the storage type of R objects before
+// converting them so that malformed sfg objects return an error
+pub(crate) fn as_doubles(x: Robj) -> Result<Doubles, SfConversionError> {
+    if x.rtype() != Rtype::Doubles {
+        return Err(SfConversionError::bad_storage("double", &x));
+    }
+
+    Doubles::try_from(x).map_err(|e| SfConversionError::BadStorageType {
+        expected: "double",
+        found: e.to_string(),
+    })
+}
+
+pub(crate) fn as_matrix(x: Robj) -> Result<RMatrix<f64>, SfConversionError> {
+    if x.rtype() != Rtype::Doubles || x.dim().map_or(true, |d| d.len() != 2) {
+        return Err(SfConversionError::bad_storage("numeric matrix", &x));
+    }
+
+    RMatrix::try_from(x).map_err(|e| SfConversionError::BadStorageType {
+        expected: "numeric matrix",
+        found: e.to_string(),
+    })
+}
+
+pub(crate) fn as_list(x: Robj) -> Result<List, SfConversionError> {
+    if x.rtype() != Rtype::List {
+        return Err(SfConversionError::bad_storage("list", &x));
+    }
+
+    List::try_from(x).map_err(|e| SfConversionError::BadStorageType {
+        expected: "list",
+        found: e.to_string(),
+    })
+}
+
+/// Checks that a polygon ring is closed and has at least 4 coordinates.
+pub fn validate_ring(x: &[Coord]) -> Result<(), SfConversionError> {
+    let n = x.len();
+    if n < 4 {
+        return Err(SfConversionError::MalformedRing(format!(
+            "rings must have at least 4 coordinates, found {n}"
+        )));
+    }
+
+    if x[0] != x[n - 1] {
+        return Err(SfConversionError::MalformedRing(String::from(
+            "the first and last coordinates of a ring must be identical",
+        )));
+    }
+
+    Ok(())
+}
+
+pub(crate) fn point_inner(x: Doubles) -> Result<Point, SfConversionError> {
+    if x.len() != 2 {
+        return Err(SfConversionError::WrongDimensions {
+            expected: 2,
+            found: x.len(),
+        });
+    }
+
+    Ok(Point::new(x[0].inner(), x[1].inner()))
+}
+
+pub(crate) fn multilinestring_inner(x: List) -> Result<MultiLineString, SfConversionError> {
+    let vec_lns = x
+        .into_iter()
+        .map(|(_, x)| Ok(LineString::new(matrix_to_coords(as_matrix(x)?)?)))
+        .collect::<Result<Vec<LineString>, SfConversionError>>()?;
+
+    Ok(MultiLineString::new(vec_lns))
+}
+
+/// Create a `Polygon` from its rings. The first ring is the exterior ring and
+/// the remaining rings are holes. No rings is an empty polygon (`POLYGON EMPTY`).
+pub fn polygon_from_rings(mut rings: Vec<LineString>) -> Polygon {
+    if rings.is_empty() {
+        return Polygon::new(LineString::new(vec![]), vec![]);
+    }
+
+    let exterior = rings.remove(0);
+    Polygon::new(exterior, rings)
 }
 
 // utility function to take a list and convert to a Polygon
 // will be used to collect into `Vec<Polygon>` and thus into `MultiPolygon`
-fn polygon_inner(x: List) -> Polygon {
-    let n = x.len();
-    let mut linestrings: Vec<LineString> = Vec::with_capacity(n);
+pub(crate) fn polygon_inner(x: List) -> Result<Polygon, SfConversionError> {
+    let rings = x
+        .into_iter()
+        .map(|(_, xi)| {
+            let coords = matrix_to_coords(as_matrix(xi)?)?;
+            validate_ring(&coords)?;
+            Ok(LineString::new(coords))
+        })
+        .collect::<Result<Vec<LineString>, SfConversionError>>()?;
+
+    Ok(polygon_from_rings(rings))
+}
+
+pub(crate) fn multipolygon_inner(x: List) -> Result<MultiPolygon, SfConversionError> {
+    let polys = x
+        .into_iter()
+        .map(|(_, xi)| polygon_inner(as_list(xi)?))
+        .collect::<Result<Vec<Polygon>, SfConversionError>>()?;
+
+    Ok(MultiPolygon::new(polys))
+}
 
-    let exterior = matrix_to_coords(x[0].as_matrix().unwrap());
-    let exterior = LineString::new(exterior);
+pub(crate) fn geometrycollection_inner(x: List) -> Result<GeometryCollection, SfConversionError> {
+    let geoms = x
+        .into_iter()
+        .map(|(_, xi)| Ok(sfg_to_geom(xi)?.geom))
+        .collect::<Result<Vec<Geometry>, SfConversionError>>()?;
 
-    if n > 1 {
-        for i in 1..n {
-            let xi: RMatrix<f64> = x[i].to_owned().try_into().unwrap();
-            let coords = matrix_to_coords(xi);
-            let line = LineString::new(coords);
-            linestrings.push(line);
+    Ok(GeometryCollection::new_from(geoms))
+}
+
+// Long format construction ----------------------------------------------------
+//
+// These builders mimic sfheaders e.g. `sfheaders::sf_linestring()`. They take
+// coordinate vectors with one row per coordinate and id vectors which assign
+// each row to a geometry or part. A new geometry or part begins whenever its
+// id changes so rows must be ordered such that each id is contiguous. The
+// resulting geometries can be passed to `to_sfc()` to create an sfc.
+
+/// Create a `Point` from each row of the `x` and `y` coordinate vectors.
+pub fn points_from_long(x: &[f64], y: &[f64]) -> Result<Vec<Geom>, SfConversionError> {
+    check_long_len(x, y)?;
+    let res = x
+        .iter()
+        .zip(y)
+        .map(|(x, y)| Geom::from(Point::new(*x, *y)))
+        .collect();
+    Ok(res)
+}
+
+/// Create a `MultiPoint` for each run of `multipoint_id`.
+pub fn multipoints_from_long<T: PartialEq>(
+    x: &[f64],
+    y: &[f64],
+    multipoint_id: &[T],
+) -> Result<Vec<Geom>, SfConversionError> {
+    check_long_len(x, multipoint_id)?;
+    check_long_len(x, y)?;
+
+    let res = id_runs(multipoint_id, 0..x.len())
+        .into_iter()
+        .map(|r| {
+            let pnts = long_coords(x, y, r).into_iter().map(Point::from).collect();
+            Geom::from(MultiPoint::new(pnts))
+        })
+        .collect();
+    Ok(res)
+}
+
+/// Create a `LineString` for each run of `linestring_id`.
+pub fn linestrings_from_long<T: PartialEq>(
+    x: &[f64],
+    y: &[f64],
+    linestring_id: &[T],
+) -> Result<Vec<Geom>, SfConversionError> {
+    check_long_len(x, linestring_id)?;
+    check_long_len(x, y)?;
+
+    let res = id_runs(linestring_id, 0..x.len())
+        .into_iter()
+        .map(|r| Geom::from(LineString::new(long_coords(x, y, r))))
+        .collect();
+    Ok(res)
+}
+
+/// Create a `MultiLineString` for each run of `multilinestring_id` whose
+/// linestrings are the runs of `linestring_id`.
+pub fn multilinestrings_from_long<T: PartialEq, U: PartialEq>(
+    x: &[f64],
+    y: &[f64],
+    linestring_id: &[T],
+    multilinestring_id: &[U],
+) -> Result<Vec<Geom>, SfConversionError> {
+    check_long_len(x, linestring_id)?;
+    check_long_len(x, multilinestring_id)?;
+    check_long_len(x, y)?;
+
+    let res = id_runs(multilinestring_id, 0..x.len())
+        .into_iter()
+        .map(|r| {
+            let lns = id_runs(linestring_id, r)
+                .into_iter()
+                .map(|ri| LineString::new(long_coords(x, y, ri)))
+                .collect();
+            Geom::from(MultiLineString::new(lns))
+        })
+        .collect();
+    Ok(res)
+}
+
+/// Create a `Polygon` for each run of `polygon_id` whose rings are the runs
+/// of `linestring_id`. The first ring is the exterior ring. As in sfheaders,
+/// rings are closed if their first and last coordinates differ.
+pub fn polygons_from_long<T: PartialEq, U: PartialEq>(
+    x: &[f64],
+    y: &[f64],
+    linestring_id: &[T],
+    polygon_id: &[U],
+) -> Result<Vec<Geom>, SfConversionError> {
+    check_long_len(x, linestring_id)?;
+    check_long_len(x, polygon_id)?;
+    check_long_len(x, y)?;
+
+    id_runs(polygon_id, 0..x.len())
+        .into_iter()
+        .map(|r| Ok(Geom::from(long_polygon(x, y, linestring_id, r)?)))
+        .collect()
+}
+
+/// Create a `MultiPolygon` for each run of `multipolygon_id` whose polygons
+/// are the runs of `polygon_id` and rings are the runs of `linestring_id`.
+/// Rings are closed if their first and last coordinates differ.
+pub fn multipolygons_from_long<T: PartialEq, U: PartialEq, V: PartialEq>(
+    x: &[f64],
+    y: &[f64],
+    linestring_id: &[T],
+    polygon_id: &[U],
+    multipolygon_id: &[V],
+) -> Result<Vec<Geom>, SfConversionError> {
+    check_long_len(x, linestring_id)?;
+    check_long_len(x, polygon_id)?;
+    check_long_len(x, multipolygon_id)?;
+    check_long_len(x, y)?;
+
+    id_runs(multipolygon_id, 0..x.len())
+        .into_iter()
+        .map(|r| {
+            let polys = id_runs(polygon_id, r)
+                .into_iter()
+                .map(|ri| long_polygon(x, y, linestring_id, ri))
+                .collect::<Result<Vec<Polygon>, SfConversionError>>()?;
+            Ok(Geom::from(MultiPolygon::new(polys)))
+        })
+        .collect()
+}
+
+// every id vector must have one element per coordinate
+fn check_long_len<T>(x: &[f64], other: &[T]) -> Result<(), SfConversionError> {
+    if x.len() != other.len() {
+        return Err(SfConversionError::LengthMismatch {
+            expected: x.len(),
+            found: other.len(),
+        });
+    }
+    Ok(())
+}
+
+// splits a range of rows into runs of identical consecutive ids
+fn id_runs<T: PartialEq>(ids: &[T], rows: Range<usize>) -> Vec<Range<usize>> {
+    let mut runs = Vec::new();
+    let mut start = rows.start;
+
+    for i in rows.clone().skip(1) {
+        if ids[i] != ids[i - 1] {
+            runs.push(start..i);
+            start = i;
         }
     }
 
-    Polygon::new(exterior, linestrings)
+    if !rows.is_empty() {
+        runs.push(start..rows.end);
+    }
+
+    runs
+}
+
+fn long_coords(x: &[f64], y: &[f64], rows: Range<usize>) -> Vec<Coord> {
+    rows.map(|i| coord! { x: x[i], y: y[i] }).collect()
+}
+
+fn long_polygon<T: PartialEq>(
+    x: &[f64],
+    y: &[f64],
+    linestring_id: &[T],
+    rows: Range<usize>,
+) -> Result<Polygon, SfConversionError> {
+    let rings = id_runs(linestring_id, rows)
+        .into_iter()
+        .map(|r| {
+            let mut coords = long_coords(x, y, r);
+            if coords.first() != coords.last() {
+                coords.push(coords[0]);
+            }
+            validate_ring(&coords)?;
+            Ok(LineString::new(coords))
+        })
+        .collect::<Result<Vec<LineString>, SfConversionError>>()?;
+
+    Ok(polygon_from_rings(rings))
 }
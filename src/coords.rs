@@ -0,0 +1,328 @@
+//! Flatten geometries into a table of coordinates
+//!
+//! [`CoordTable`] has the same layout as `sf::st_coordinates()`. Each row is a
+//! coordinate with `X` and `Y` columns, `Z` and `M` columns when present, and
+//! `L1`, `L2`, and `L3` columns which index the part the coordinate belongs to:
+//!
+//! - `POINT`: no index columns
+//! - `MULTIPOINT` and `LINESTRING`: `L1` is the feature
+//! - `MULTILINESTRING`: `L1` is the linestring and `L2` is the feature
+//! - `POLYGON`: `L1` is the ring and `L2` is the feature
+//! - `MULTIPOLYGON`: `L1` is the ring, `L2` is the polygon, and `L3` is the feature
+//!
+//! Indices are 1-based. As in sf, every geometry must have the same sfg type
+//! (see [`sfg_type()`]) and `GEOMETRYCOLLECTION`s are not supported.
+//!
+//! Missing geometries have no coordinates but still advance the feature index.
+//! Points are the exception: as they have no index column, a missing or empty
+//! point is a row of `NA` so that each row is a feature.
+//!
+//! This is the inverse of the long format builders in
+//! [`constructors`](crate::constructors) such as `linestrings_from_long()`.
+use crate::{
+    error::SfConversionError,
+    fromsf::sfg_to_geom_zm,
+    geometry_is_empty,
+    tosf::sfg_type,
+    zm::{Dim, GeomZM, OrdinateWriter},
+    Geom,
+};
+use extendr_api::prelude::*;
+use geo::CoordsIter;
+use geo_types::{Coord, Geometry, Polygon};
+use std::result::Result;
+
+/// The coordinates of a vector of geometries in the layout of `sf::st_coordinates()`
+#[derive(Debug, Clone)]
+pub struct CoordTable {
+    /// the coordinate dimension of the geometries
+    pub dim: Dim,
+    /// X ordinates
+    pub x: Vec<f64>,
+    /// Y ordinates
+    pub y: Vec<f64>,
+    /// Z ordinates. Empty when the dimension has no Z.
+    pub z: Vec<f64>,
+    /// M ordinates. Empty when the dimension has no M.
+    pub m: Vec<f64>,
+    /// the 1-based `L1`, `L2`, and `L3` index columns in order
+    pub l: Vec<Vec<i32>>,
+}
+
+impl CoordTable {
+    fn new(dim: Dim, n_levels: usize) -> Self {
+        CoordTable {
+            dim,
+            x: Vec::new(),
+            y: Vec::new(),
+            z: Vec::new(),
+            m: Vec::new(),
+            l: vec![Vec::new(); n_levels],
+        }
+    }
+
+    /// The number of coordinates
+    pub fn nrows(&self) -> usize {
+        self.x.len()
+    }
+
+    /// The column names e.g. `c("X", "Y", "L1", "L2")`
+    pub fn names(&self) -> Vec<String> {
+        let mut names = vec!["X".to_string(), "Y".to_string()];
+
+        if self.dim.has_z() {
+            names.push("Z".to_string());
+        }
+
+        if self.dim.has_m() {
+            names.push("M".to_string());
+        }
+
+        names.extend((1..=self.l.len()).map(|i| format!("L{i}")));
+        names
+    }
+
+    /// Create a double matrix with column names as returned by `sf::st_coordinates()`
+    pub fn to_matrix(&self) -> Robj {
+        let cols = self.columns();
+        let names = self.names();
+
+        let mut res: Robj = RMatrix::new_matrix(self.nrows(), cols.len(), |r, c| cols[c][r]).into();
+        res.set_attrib(
+            "dimnames",
+            List::from_values([Robj::from(NULL), Robj::from(names)]),
+        )
+        .unwrap()
+        .clone()
+    }
+
+    /// Create a data.frame. The ordinate columns are doubles whereas the
+    /// index columns are integers.
+    pub fn to_data_frame(&self) -> Robj {
+        let mut values = vec![Robj::from(self.x.clone()), Robj::from(self.y.clone())];
+
+        if self.dim.has_z() {
+            values.push(Robj::from(self.z.clone()));
+        }
+
+        if self.dim.has_m() {
+            values.push(Robj::from(self.m.clone()));
+        }
+
+        values.extend(self.l.iter().map(|li| Robj::from(li.clone())));
+
+        List::from_names_and_values(self.names(), values)
+            .unwrap()
+            .into_robj()
+            .set_attrib(
+                "row.names",
+                Integers::from_values([Rint::na(), Rint::from(-(self.nrows() as i32))]),
+            )
+            .unwrap()
+            .set_class(["data.frame"])
+            .unwrap()
+            .clone()
+    }
+
+    // every column as doubles for the matrix representation
+    fn columns(&self) -> Vec<Vec<f64>> {
+        let mut cols = vec![self.x.clone(), self.y.clone()];
+
+        if self.dim.has_z() {
+            cols.push(self.z.clone());
+        }
+
+        if self.dim.has_m() {
+            cols.push(self.m.clone());
+        }
+
+        cols.extend(
+            self.l
+                .iter()
+                .map(|li| li.iter().map(|i| *i as f64).collect()),
+        );
+        cols
+    }
+
+    fn push_row(&mut self, row: Vec<f64>, ids: &[i32]) {
+        let mut row = row.into_iter();
+        self.x.push(row.next().unwrap());
+        self.y.push(row.next().unwrap());
+
+        if self.dim.has_z() {
+            self.z.push(row.next().unwrap());
+        }
+
+        if self.dim.has_m() {
+            self.m.push(row.next().unwrap());
+        }
+
+        for (li, id) in self.l.iter_mut().zip(ids) {
+            li.push(*id);
+        }
+    }
+
+    fn push_coords(
+        &mut self,
+        coords: impl Iterator<Item = Coord>,
+        writer: &mut OrdinateWriter,
+        ids: &[i32],
+    ) {
+        for crd in coords {
+            self.push_row(writer.row(crd), ids);
+        }
+    }
+
+    // rings are numbered within the polygon and followed by the outer indices
+    fn push_polygon(&mut self, x: &Polygon, writer: &mut OrdinateWriter, outer: &[i32]) {
+        let rings = std::iter::once(x.exterior()).chain(x.interiors());
+        for (i, ring) in rings.enumerate() {
+            let mut ids = vec![i as i32 + 1];
+            ids.extend_from_slice(outer);
+            self.push_coords(ring.0.iter().copied(), writer, &ids);
+        }
+    }
+
+    // a `Rect` or `Triangle` is a polygon with a single ring
+    fn push_corners(
+        &mut self,
+        corners: impl Iterator<Item = Coord>,
+        writer: &mut OrdinateWriter,
+        feature: i32,
+    ) {
+        for row in writer.closed_ring(corners) {
+            self.push_row(row, &[1, feature]);
+        }
+    }
+
+    fn push_geometry(&mut self, x: &Geometry, writer: &mut OrdinateWriter, feature: i32) {
+        match x {
+            Geometry::Point(p) => {
+                let mut row = writer.row(p.0);
+                if geometry_is_empty(x) {
+                    row[0] = Rfloat::na().inner();
+                    row[1] = Rfloat::na().inner();
+                }
+                self.push_row(row, &[]);
+            }
+            Geometry::MultiPoint(x) => {
+                self.push_coords(x.0.iter().map(|p| p.0), writer, &[feature])
+            }
+            Geometry::LineString(x) => self.push_coords(x.0.iter().copied(), writer, &[feature]),
+            Geometry::Line(x) => self.push_coords([x.start, x.end].into_iter(), writer, &[feature]),
+            Geometry::MultiLineString(x) => {
+                for (i, lns) in x.0.iter().enumerate() {
+                    self.push_coords(lns.0.iter().copied(), writer, &[i as i32 + 1, feature]);
+                }
+            }
+            Geometry::Polygon(x) => self.push_polygon(x, writer, &[feature]),
+            Geometry::Rect(x) => self.push_corners(x.coords_iter(), writer, feature),
+            Geometry::Triangle(x) => self.push_corners(x.coords_iter(), writer, feature),
+            Geometry::MultiPolygon(x) => {
+                for (i, poly) in x.0.iter().enumerate() {
+                    self.push_polygon(poly, writer, &[i as i32 + 1, feature]);
+                }
+            }
+            // rejected by `coords_levels()`
+            Geometry::GeometryCollection(_) => unreachable!(),
+        }
+    }
+
+    // a missing point is a row of NA
+    fn push_missing_point(&mut self) {
+        let row = vec![Rfloat::na().inner(); self.dim.n_dims()];
+        self.push_row(row, &[]);
+    }
+}
+
+/// Flatten `XY` geometries into a [`CoordTable`]
+pub fn geoms_to_coords(x: &[Option<Geom>]) -> Result<CoordTable, SfConversionError> {
+    let geoms = x
+        .iter()
+        .map(|xi| xi.as_ref().map(|g| (&g.geom, OrdinateWriter::xy())))
+        .collect();
+
+    flatten(geoms, Dim::XY)
+}
+
+/// Flatten geometries with Z and M ordinates into a [`CoordTable`]. Every
+/// geometry must have the same coordinate dimension.
+pub fn geoms_zm_to_coords(x: &[Option<GeomZM>]) -> Result<CoordTable, SfConversionError> {
+    let mut present = x.iter().flatten();
+    let dim = present.next().map_or(Dim::XY, |g| g.dim);
+
+    if let Some(g) = present.find(|g| g.dim != dim) {
+        return Err(SfConversionError::WrongDimensions {
+            expected: dim.n_dims(),
+            found: g.dim.n_dims(),
+        });
+    }
+
+    let geoms = x
+        .iter()
+        .map(|xi| {
+            xi.as_ref()
+                .map(|g| (&g.geom.geom, OrdinateWriter::new(g.dim, &g.z, &g.m)))
+        })
+        .collect();
+
+    flatten(geoms, dim)
+}
+
+/// Flatten an sfc object into a [`CoordTable`] retaining any Z and M ordinates.
+/// Use [`CoordTable::to_matrix()`] for the equivalent of `sf::st_coordinates()`.
+pub fn sfc_to_coords(x: List) -> Result<CoordTable, SfConversionError> {
+    let geoms = x
+        .into_iter()
+        .map(|(_, robj)| {
+            if robj.is_null() {
+                Ok(None)
+            } else {
+                sfg_to_geom_zm(robj).map(Some)
+            }
+        })
+        .collect::<Result<Vec<Option<GeomZM>>, SfConversionError>>()?;
+
+    geoms_zm_to_coords(&geoms)
+}
+
+// the number of index columns for the geometries. All geometries must share an sfg type.
+fn coords_levels<'a>(
+    mut x: impl Iterator<Item = &'a Geometry>,
+) -> Result<usize, SfConversionError> {
+    let typ = match x.next() {
+        Some(geom) => sfg_type(geom),
+        None => return Ok(0),
+    };
+
+    if x.any(|geom| sfg_type(geom) != typ) {
+        return Err(SfConversionError::UnsupportedType("GEOMETRY".to_string()));
+    }
+
+    match typ {
+        "POINT" => Ok(0),
+        "MULTIPOINT" | "LINESTRING" => Ok(1),
+        "MULTILINESTRING" | "POLYGON" => Ok(2),
+        "MULTIPOLYGON" => Ok(3),
+        typ => Err(SfConversionError::UnsupportedType(typ.to_string())),
+    }
+}
+
+fn flatten(
+    x: Vec<Option<(&Geometry, OrdinateWriter)>>,
+    dim: Dim,
+) -> Result<CoordTable, SfConversionError> {
+    let n_levels = coords_levels(x.iter().flatten().map(|(geom, _)| *geom))?;
+    let is_point = n_levels == 0 && x.iter().any(|xi| xi.is_some());
+    let mut res = CoordTable::new(dim, n_levels);
+
+    for (i, xi) in x.into_iter().enumerate() {
+        match xi {
+            Some((geom, mut writer)) => res.push_geometry(geom, &mut writer, i as i32 + 1),
+            None if is_point => res.push_missing_point(),
+            None => (),
+        }
+    }
+
+    Ok(res)
+}
@@ -0,0 +1,196 @@
+//! Coordinate reference system metadata of sfc objects
+//!
+//! sfc objects carry a `crs` attribute which is a list with class `crs` and two
+//! character elements: `input` (the user input such as `"EPSG:4326"`) and `wkt`
+//! (the WKT2 representation of the CRS). An unknown CRS has `NA` for both.
+//!
+//! sfconversions does not interpret the CRS, it only carries it so that it
+//! survives a round trip through Rust.
+use crate::error::SfConversionError;
+use extendr_api::prelude::*;
+
+/// The coordinate reference system of an sfc object. Missing values
+/// (`NA_character_` in R) are represented as `None`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Crs {
+    /// the user input used to create the CRS e.g. `"EPSG:4326"`
+    pub input: Option<String>,
+    /// the WKT representation of the CRS
+    pub wkt: Option<String>,
+}
+
+impl Crs {
+    pub fn new(input: Option<String>, wkt: Option<String>) -> Self {
+        Crs { input, wkt }
+    }
+
+    /// Returns `true` if the CRS is unknown
+    pub fn is_na(&self) -> bool {
+        self.input.is_none() && self.wkt.is_none()
+    }
+
+    /// Create a CRS from an EPSG code e.g. `4326` becomes `"EPSG:4326"`.
+    /// The WKT is left missing as sfconversions does not resolve CRS definitions.
+    pub fn from_epsg(code: i32) -> Self {
+        Crs {
+            input: Some(format!("EPSG:{code}")),
+            wkt: None,
+        }
+    }
+
+    /// The EPSG code of the CRS. This is read from an input of the form
+    /// `"EPSG:4326"` or otherwise from the top-level `ID["EPSG",4326]` (or WKT1
+    /// `AUTHORITY["EPSG","4326"]`) of the WKT. CRSs read from files by sf often
+    /// have an input such as `"WGS 84"` with the code only present in the WKT.
+    pub fn epsg(&self) -> Option<i32> {
+        let from_input = self.input.as_deref().and_then(|input| {
+            let (authority, code) = input.split_once(':')?;
+            authority_code(authority, code)
+        });
+
+        from_input.or_else(|| wkt_epsg(self.wkt.as_deref()?))
+    }
+}
+
+// the code of an EPSG authority e.g. `("EPSG", "4326")`
+fn authority_code(authority: &str, code: &str) -> Option<i32> {
+    let authority = authority.trim().trim_matches('"');
+    if authority.eq_ignore_ascii_case("epsg") {
+        code.trim().trim_matches('"').parse().ok()
+    } else {
+        None
+    }
+}
+
+// The EPSG code of the first `ID` or `AUTHORITY` node that is a direct child
+// of the root node. The identifiers of nested nodes such as the base CRS of a
+// projected CRS are ignored. Quoted strings are skipped as they may contain
+// brackets.
+fn wkt_epsg(wkt: &str) -> Option<i32> {
+    let mut depth = 0;
+    let mut in_quotes = false;
+
+    for (i, c) in wkt.char_indices() {
+        match c {
+            '"' => in_quotes = !in_quotes,
+            _ if in_quotes => (),
+            '[' | '(' => {
+                let keyword = wkt[..i]
+                    .trim_end()
+                    .rsplit(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                    .next()
+                    .unwrap_or_default();
+
+                let is_id = ["ID", "AUTHORITY"]
+                    .iter()
+                    .any(|k| keyword.eq_ignore_ascii_case(k));
+
+                if depth == 1 && is_id {
+                    let body = wkt[i + 1..].split([']', ')']).next()?;
+                    let mut values = body.split(',');
+                    if let Some(code) = authority_code(values.next()?, values.next()?) {
+                        return Some(code);
+                    }
+                }
+
+                depth += 1;
+            }
+            ']' | ')' => depth -= 1,
+            _ => (),
+        }
+    }
+
+    None
+}
+
+// extract a scalar character element from a crs list
+fn crs_element(x: &List, name: &str) -> Option<String> {
+    let (_, val) = x.iter().find(|(nm, _)| *nm == name)?;
+    let val = Strings::try_from(val).ok()?;
+    let val = val.iter().next()?;
+
+    if val.is_na() {
+        None
+    } else {
+        Some(val.as_str().to_string())
+    }
+}
+
+/// Fallibly parse a `crs` class object
+impl TryFrom<Robj> for Crs {
+    type Error = SfConversionError;
+
+    fn try_from(x: Robj) -> std::result::Result<Self, Self::Error> {
+        if !x.inherits("crs") {
+            return Err(SfConversionError::MissingClass("crs"));
+        }
+
+        let x = List::try_from(x).map_err(|e| SfConversionError::BadStorageType {
+            expected: "list",
+            found: e.to_string(),
+        })?;
+        Ok(Crs {
+            input: crs_element(&x, "input"),
+            wkt: crs_element(&x, "wkt"),
+        })
+    }
+}
+
+/// Create a `crs` class object. `None` values are `NA_character_`.
+impl From<&Crs> for Robj {
+    fn from(crs: &Crs) -> Self {
+        let chr = |x: &Option<String>| match x {
+            Some(x) => Robj::from(x.as_str()),
+            None => Robj::from(Strings::from_values([Rstr::na()])),
+        };
+
+        List::from_names_and_values(["input", "wkt"], [chr(&crs.input), chr(&crs.wkt)])
+            .unwrap()
+            .into_robj()
+            .set_class(["crs"])
+            .unwrap()
+            .clone()
+    }
+}
+
+impl From<Crs> for Robj {
+    fn from(crs: Crs) -> Self {
+        Robj::from(&crs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn epsg_is_read_from_the_input_or_wkt() {
+        assert_eq!(Crs::from_epsg(3857).epsg(), Some(3857));
+
+        let wkt2 = r#"GEOGCRS["WGS 84",
+            DATUM["World Geodetic System 1984",
+                ELLIPSOID["WGS 84",6378137,298.257223563,LENGTHUNIT["metre",1]]],
+            CS[ellipsoidal,2],
+            ID["EPSG",4326]]"#;
+        let crs = Crs::new(Some("WGS 84".to_string()), Some(wkt2.to_string()));
+        assert_eq!(crs.epsg(), Some(4326));
+
+        let wkt1 = r#"PROJCS["NAD83 / UTM zone 17N",
+            GEOGCS["NAD83",AUTHORITY["EPSG","4269"]],
+            PROJECTION["Transverse_Mercator"],
+            AUTHORITY["EPSG","26917"]]"#;
+        let crs = Crs::new(None, Some(wkt1.to_string()));
+        assert_eq!(crs.epsg(), Some(26917));
+    }
+
+    #[test]
+    fn nested_and_non_epsg_ids_are_ignored() {
+        let wkt = r#"PROJCRS["custom [projection]",
+            BASEGEOGCRS["WGS 84",ID["EPSG",4326]],
+            CONVERSION["custom",METHOD["Transverse Mercator",ID["EPSG",9807]]],
+            ID["ESRI",102003]]"#;
+        let crs = Crs::new(Some("custom".to_string()), Some(wkt.to_string()));
+        assert_eq!(crs.epsg(), None);
+        assert_eq!(Crs::default().epsg(), None);
+    }
+}
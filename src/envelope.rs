@@ -0,0 +1,87 @@
+//! Envelopes of `Geom` for rstar
+//!
+//! `Geom` implements rstar's `RTreeObject` so that geometries can be stored in
+//! an `RTree`. geo's `BoundingRect` is not defined for every geometry: empty
+//! geometries have no bounding rectangle and `POINT EMPTY`, a point with `NaN`
+//! coordinates, has a `NaN` one. Collections containing empty geometries are
+//! affected in the same way.
+//!
+//! The envelope is instead calculated from the non-`NaN` coordinates of the
+//! geometry. A geometry with no such coordinates has no envelope and
+//! [`Geom::to_aabb()`] returns `None`. Its `RTreeObject` envelope is an explicit
+//! empty `AABB` which never intersects another envelope. It is, however,
+//! contained by every envelope so `RTree::locate_in_envelope()` on an
+//! `RTree<Geom>` can return geometries without an envelope. Filter them with
+//! `to_aabb()` or keep them out of the tree. Points, and vertical or horizontal
+//! lines, have a degenerate envelope with no area which is valid.
+//!
+//! The [`SpatialIndex`](crate::index::SpatialIndex) excludes geometries
+//! without an envelope entirely and retains the indices of the others.
+//!
+//! This module is named `envelope` rather than `rstar` so that `rstar::` paths
+//! within sfconversions refer to the rstar crate.
+use crate::Geom;
+use geo::CoordsIter;
+use rstar::{Envelope, AABB};
+
+impl Geom {
+    /// The envelope of the geometry as an `AABB`. `None` if the geometry has
+    /// no coordinates other than `NaN`s e.g. an empty geometry.
+    pub fn to_aabb(&self) -> Option<AABB<[f64; 2]>> {
+        let mut coords = self
+            .geom
+            .coords_iter()
+            .filter(|crd| !(crd.x.is_nan() || crd.y.is_nan()))
+            .map(|crd| [crd.x, crd.y]);
+
+        let first = coords.next()?;
+        let res = coords.fold(AABB::from_point(first), |mut acc, crd| {
+            acc.merge(&AABB::from_point(crd));
+            acc
+        });
+
+        Some(res)
+    }
+}
+
+/// Implement RTreeObject for Geom. Geometries without an envelope have an
+/// empty `AABB` which is contained by every envelope. See the module docs.
+impl rstar::RTreeObject for Geom {
+    type Envelope = AABB<[f64; 2]>;
+    fn envelope(&self) -> Self::Envelope {
+        self.to_aabb().unwrap_or_else(AABB::new_empty)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use geo_types::{point, Geometry, GeometryCollection, LineString, Point};
+    use rstar::{RTree, RTreeObject};
+
+    #[test]
+    fn nan_coordinates_are_ignored() {
+        let x = Geom::from(GeometryCollection::new_from(vec![
+            Point::new(f64::NAN, f64::NAN).into(),
+            point! { x: 1.0, y: 2.0 }.into(),
+            LineString::from(vec![(3.0, -1.0), (4.0, 0.0)]).into(),
+        ]));
+
+        let aabb = x.to_aabb().unwrap();
+        assert_eq!(aabb.lower(), [1.0, -1.0]);
+        assert_eq!(aabb.upper(), [4.0, 2.0]);
+    }
+
+    #[test]
+    fn empty_geometries_are_only_found_by_containment() {
+        let empty = Geom::from(Geometry::from(LineString::new(vec![])));
+        assert!(empty.to_aabb().is_none());
+        assert_eq!(empty.envelope(), AABB::new_empty());
+
+        let tree = RTree::bulk_load(vec![empty, Geom::from(point! { x: 1.0, y: 1.0 })]);
+        let query = AABB::from_corners([0.0, 0.0], [2.0, 2.0]);
+
+        assert_eq!(tree.locate_in_envelope_intersecting(&query).count(), 1);
+        assert_eq!(tree.locate_in_envelope(&query).count(), 2);
+    }
+}
@@ -0,0 +1,100 @@
+//! Errors returned by sfconversions
+//!
+//! All fallible conversions return an `SfConversionError`. It can be converted
+//! into an `extendr_api::Error` so that it may be propagated with `?` from
+//! `#[extendr]` functions and raised as an R error rather than aborting the session.
+use extendr_api::prelude::*;
+use std::fmt;
+
+/// The error type of all fallible conversions in sfconversions.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SfConversionError {
+    /// The geometry type is not supported by the conversion e.g. `"CIRCULARSTRING"`
+    UnsupportedType(String),
+    /// A coordinate matrix or vector has the wrong number of ordinates
+    WrongDimensions { expected: usize, found: usize },
+    /// The number of values does not match the number of coordinates
+    LengthMismatch { expected: usize, found: usize },
+    /// A polygon ring is not closed or has too few coordinates
+    MalformedRing(String),
+    /// The R object does not have the expected storage type
+    BadStorageType {
+        expected: &'static str,
+        found: String,
+    },
+    /// The R object is missing an expected class e.g. `"sfg"`
+    MissingClass(&'static str),
+    /// A serialized geometry (e.g. JSON or WKT) could not be parsed
+    Parse(String),
+    /// A record or list is missing a required field e.g. the `xmin` of a `wk_rct`
+    MissingField(String),
+    /// The R object is missing a required attribute e.g. the `sf_column` of an sf data.frame
+    MissingAttribute(&'static str),
+    /// A data.frame would have two columns with the same name
+    DuplicateColumn(String),
+    /// An index into a geometry vector is missing or out of bounds
+    InvalidIndex(String),
+    /// The geometry is not the requested geo-types type e.g. a `MultiPolygon`
+    /// was found when extracting a `Polygon`
+    MismatchedGeometry {
+        expected: &'static str,
+        found: &'static str,
+    },
+}
+
+impl fmt::Display for SfConversionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SfConversionError::UnsupportedType(typ) => {
+                write!(f, "unsupported geometry type `{typ}`")
+            }
+            SfConversionError::WrongDimensions { expected, found } => {
+                write!(
+                    f,
+                    "expected {expected} ordinates per coordinate, found {found}"
+                )
+            }
+            SfConversionError::LengthMismatch { expected, found } => {
+                write!(f, "expected {expected} values, found {found}")
+            }
+            SfConversionError::MalformedRing(msg) => write!(f, "malformed ring: {msg}"),
+            SfConversionError::BadStorageType { expected, found } => {
+                write!(f, "expected storage type `{expected}`, found `{found}`")
+            }
+            SfConversionError::MissingClass(cls) => {
+                write!(f, "object does not have class `{cls}`")
+            }
+            SfConversionError::Parse(msg) => write!(f, "failed to parse geometry: {msg}"),
+            SfConversionError::MissingField(name) => write!(f, "missing field `{name}`"),
+            SfConversionError::MissingAttribute(name) => {
+                write!(f, "object does not have attribute `{name}`")
+            }
+            SfConversionError::DuplicateColumn(name) => {
+                write!(f, "column `{name}` is duplicated")
+            }
+            SfConversionError::InvalidIndex(msg) => write!(f, "invalid index: {msg}"),
+            SfConversionError::MismatchedGeometry { expected, found } => {
+                write!(f, "expected a `{expected}` geometry, found `{found}`")
+            }
+        }
+    }
+}
+
+impl std::error::Error for SfConversionError {}
+
+/// Raise an `SfConversionError` as an R error from `#[extendr]` functions
+impl From<SfConversionError> for Error {
+    fn from(e: SfConversionError) -> Self {
+        Error::Other(e.to_string())
+    }
+}
+
+impl SfConversionError {
+    // error for an R object with an unexpected storage type
+    pub(crate) fn bad_storage(expected: &'static str, x: &Robj) -> Self {
+        SfConversionError::BadStorageType {
+            expected,
+            found: format!("{:?}", x.rtype()),
+        }
+    }
+}
@@ -0,0 +1,371 @@
+//! Convert between `Geom` and Esri JSON geometry objects
+//!
+//! Esri JSON is the geometry format used by ArcGIS REST services. The
+//! following geometry objects are supported:
+//!
+//! - point: `{"x": 1, "y": 2}`
+//! - multipoint: `{"points": [[1, 2], [3, 4]]}`
+//! - polyline: `{"paths": [[[1, 2], [3, 4]]]}`
+//! - polygon: `{"rings": [[[0, 0], [0, 1], [1, 1], [0, 0]]]}`
+//! - envelope: `{"xmin": 0, "ymin": 0, "xmax": 1, "ymax": 1}`
+//!
+//! Esri JSON does not distinguish between single and multi part geometries.
+//! Polylines are always read as a `MultiLineString` and polygons as a
+//! `MultiPolygon`. Polygon rings are split into polygons and holes by their
+//! orientation: clockwise rings are exterior rings and counter-clockwise rings
+//! are holes of the smallest exterior ring that contains them. When writing, ring
+//! orientation is corrected to follow this convention.
+//!
+//! Only two dimensional geometries can be read. Geometries with `hasZ` or
+//! `hasM` set, or with Z or M values, return [`SfConversionError::WrongDimensions`].
+use crate::{constructors::validate_ring, crs::Crs, error::SfConversionError, geometry_name, Geom};
+use geo::{Area, Contains, Winding};
+use geo_types::{
+    coord, Coord, Geometry, LineString, MultiLineString, MultiPoint, MultiPolygon, Point, Polygon,
+    Rect,
+};
+use serde_json::{json, Map, Value};
+use std::result::Result;
+
+/// Convert a `Geom` into an Esri JSON geometry object. The `spatialReference`
+/// is written when the CRS has an EPSG code or a WKT definition.
+///
+/// `GeometryCollection`s cannot be represented in Esri JSON and return an error.
+pub fn geom_to_esri(x: &Geom, crs: &Crs) -> Result<Value, SfConversionError> {
+    let mut obj = geometry_to_esri(&x.geom)?;
+
+    if let (Some(sr), Value::Object(map)) = (spatial_reference(crs), &mut obj) {
+        map.insert("spatialReference".to_string(), sr);
+    }
+
+    Ok(obj)
+}
+
+/// Convert a `Geom` into an Esri JSON string.
+pub fn geom_to_esri_string(x: &Geom, crs: &Crs) -> Result<String, SfConversionError> {
+    Ok(geom_to_esri(x, crs)?.to_string())
+}
+
+/// Convert a `Geometry` into an Esri JSON geometry object without a `spatialReference`.
+pub fn geometry_to_esri(x: &Geometry) -> Result<Value, SfConversionError> {
+    let res = match x {
+        Geometry::Point(x) => point_to_esri(x),
+        Geometry::MultiPoint(x) => json!({ "points": points_to_esri(x.iter()) }),
+        Geometry::Line(x) => json!({ "paths": [coords_to_esri([x.start, x.end].into_iter())] }),
+        Geometry::LineString(x) => json!({ "paths": [coords_to_esri(x.coords().copied())] }),
+        Geometry::MultiLineString(x) => json!({
+            "paths": x.iter().map(|l| coords_to_esri(l.coords().copied())).collect::<Vec<Value>>()
+        }),
+        Geometry::Polygon(x) => json!({ "rings": polygon_rings(x) }),
+        Geometry::MultiPolygon(x) => json!({
+            "rings": x.iter().flat_map(polygon_rings).collect::<Vec<Value>>()
+        }),
+        Geometry::Triangle(x) => json!({ "rings": polygon_rings(&x.to_polygon()) }),
+        Geometry::Rect(x) => json!({
+            "xmin": x.min().x,
+            "ymin": x.min().y,
+            "xmax": x.max().x,
+            "ymax": x.max().y
+        }),
+        Geometry::GeometryCollection(_) => {
+            return Err(SfConversionError::UnsupportedType(
+                geometry_name(x).to_string(),
+            ))
+        }
+    };
+
+    Ok(res)
+}
+
+/// The Esri JSON `spatialReference` of a CRS. An EPSG code is written as a
+/// `wkid` otherwise the `wkt` is used. Returns `None` if neither are available.
+pub fn spatial_reference(crs: &Crs) -> Option<Value> {
+    if let Some(wkid) = crs.epsg() {
+        return Some(json!({ "wkid": wkid }));
+    }
+
+    crs.wkt.as_ref().map(|wkt| json!({ "wkt": wkt }))
+}
+
+/// Read the CRS from the `spatialReference` of an Esri JSON geometry object.
+/// The `latestWkid` is preferred over the `wkid`.
+pub fn esri_crs(x: &Value) -> Crs {
+    let sr = match x.get("spatialReference") {
+        Some(sr) => sr,
+        None => return Crs::default(),
+    };
+
+    let wkid = sr
+        .get("latestWkid")
+        .or_else(|| sr.get("wkid"))
+        .and_then(Value::as_i64);
+
+    match wkid {
+        Some(wkid) => Crs::from_epsg(wkid as i32),
+        None => Crs::new(
+            None,
+            sr.get("wkt").and_then(Value::as_str).map(String::from),
+        ),
+    }
+}
+
+/// Convert an Esri JSON geometry object into a `Geom`. The geometry type is
+/// determined by the fields present in the object.
+pub fn esri_to_geom(x: &Value) -> Result<Geom, SfConversionError> {
+    let obj = x
+        .as_object()
+        .ok_or_else(|| SfConversionError::Parse("Esri geometry must be a JSON object".into()))?;
+
+    check_esri_dims(obj)?;
+
+    let geom: Geometry = if obj.contains_key("x") {
+        esri_to_point(obj)?.into()
+    } else if let Some(points) = obj.get("points") {
+        let coords = esri_to_coords(points)?;
+        MultiPoint::new(coords.into_iter().map(Point::from).collect()).into()
+    } else if let Some(paths) = obj.get("paths") {
+        let lines = esri_array(paths)?
+            .iter()
+            .map(|p| Ok(LineString::new(esri_to_coords(p)?)))
+            .collect::<Result<Vec<LineString>, SfConversionError>>()?;
+        MultiLineString::new(lines).into()
+    } else if let Some(rings) = obj.get("rings") {
+        let rings = esri_array(rings)?
+            .iter()
+            .map(|r| {
+                let coords = esri_to_coords(r)?;
+                validate_ring(&coords)?;
+                Ok(LineString::new(coords))
+            })
+            .collect::<Result<Vec<LineString>, SfConversionError>>()?;
+        rings_to_multipolygon(rings).into()
+    } else if obj.contains_key("xmin") {
+        esri_to_rect(obj)?.into()
+    } else {
+        return Err(SfConversionError::Parse(
+            "unrecognised Esri geometry object".into(),
+        ));
+    };
+
+    Ok(Geom::from(geom))
+}
+
+/// Parse an Esri JSON string into a `Geom`.
+pub fn esri_string_to_geom(x: &str) -> Result<Geom, SfConversionError> {
+    let value: Value =
+        serde_json::from_str(x).map_err(|e| SfConversionError::Parse(e.to_string()))?;
+    esri_to_geom(&value)
+}
+
+// empty points are written with a null x value
+fn point_to_esri(x: &Point) -> Value {
+    if x.x().is_nan() && x.y().is_nan() {
+        json!({ "x": Value::Null, "y": Value::Null })
+    } else {
+        json!({ "x": x.x(), "y": x.y() })
+    }
+}
+
+fn points_to_esri<'a>(x: impl Iterator<Item = &'a Point>) -> Value {
+    coords_to_esri(x.map(|p| p.0))
+}
+
+fn coords_to_esri(x: impl Iterator<Item = Coord>) -> Value {
+    Value::Array(x.map(|c| json!([c.x, c.y])).collect())
+}
+
+// exterior rings are written clockwise and interior rings counter-clockwise
+fn polygon_rings(x: &Polygon) -> Vec<Value> {
+    if x.exterior().0.is_empty() {
+        return Vec::new();
+    }
+
+    let mut exterior = x.exterior().clone();
+    exterior.make_cw_winding();
+
+    let mut rings = vec![coords_to_esri(exterior.coords().copied())];
+
+    for interior in x.interiors() {
+        let mut interior = interior.clone();
+        interior.make_ccw_winding();
+        rings.push(coords_to_esri(interior.coords().copied()));
+    }
+
+    rings
+}
+
+// Z and M values are flagged by `hasZ` and `hasM` or, for points and
+// envelopes, present as fields
+fn check_esri_dims(x: &Map<String, Value>) -> Result<(), SfConversionError> {
+    let flag = |key: &str| x.get(key).and_then(Value::as_bool).unwrap_or(false);
+    let has_z = flag("hasZ") || x.contains_key("z") || x.contains_key("zmin");
+    let has_m = flag("hasM") || x.contains_key("m") || x.contains_key("mmin");
+
+    if has_z || has_m {
+        return Err(SfConversionError::WrongDimensions {
+            expected: 2,
+            found: 2 + has_z as usize + has_m as usize,
+        });
+    }
+
+    Ok(())
+}
+
+fn esri_array(x: &Value) -> Result<&Vec<Value>, SfConversionError> {
+    x.as_array()
+        .ok_or_else(|| SfConversionError::Parse(format!("expected an array, found `{x}`")))
+}
+
+fn esri_number(x: Option<&Value>) -> Result<f64, SfConversionError> {
+    match x {
+        Some(Value::Number(n)) => Ok(n.as_f64().unwrap_or(f64::NAN)),
+        Some(Value::Null) => Ok(f64::NAN),
+        Some(Value::String(s)) if s == "NaN" => Ok(f64::NAN),
+        Some(x) => Err(SfConversionError::Parse(format!(
+            "expected a number, found `{x}`"
+        ))),
+        None => Err(SfConversionError::Parse("missing ordinate".into())),
+    }
+}
+
+// coordinates are arrays of two numbers
+fn esri_to_coords(x: &Value) -> Result<Vec<Coord>, SfConversionError> {
+    esri_array(x)?
+        .iter()
+        .map(|crd| {
+            let crd = esri_array(crd)?;
+            if crd.len() != 2 {
+                return Err(SfConversionError::WrongDimensions {
+                    expected: 2,
+                    found: crd.len(),
+                });
+            }
+            Ok(coord! { x: esri_number(crd.first())?, y: esri_number(crd.get(1))? })
+        })
+        .collect()
+}
+
+// a null or NaN x value is an empty point
+fn esri_to_point(x: &Map<String, Value>) -> Result<Point, SfConversionError> {
+    let px = esri_number(x.get("x"))?;
+    if px.is_nan() {
+        return Ok(Point::new(f64::NAN, f64::NAN));
+    }
+
+    Ok(Point::new(px, esri_number(x.get("y"))?))
+}
+
+fn esri_to_rect(x: &Map<String, Value>) -> Result<Rect, SfConversionError> {
+    let xmin = esri_number(x.get("xmin"))?;
+    let ymin = esri_number(x.get("ymin"))?;
+    let xmax = esri_number(x.get("xmax"))?;
+    let ymax = esri_number(x.get("ymax"))?;
+
+    if [xmin, ymin, xmax, ymax].iter().any(|v| v.is_nan()) {
+        return Err(SfConversionError::Parse(
+            "empty envelopes cannot be represented as a Rect".into(),
+        ));
+    }
+
+    Ok(Rect::new(
+        coord! { x: xmin, y: ymin },
+        coord! { x: xmax, y: ymax },
+    ))
+}
+
+// Clockwise rings start a new polygon. Counter-clockwise rings are holes
+// of the smallest polygon whose exterior contains them so that holes of
+// islands nested within holes are assigned to the island. Holes that are
+// not contained by any exterior ring are treated as polygons of their own.
+fn rings_to_multipolygon(rings: Vec<LineString>) -> MultiPolygon {
+    let mut shells: Vec<Polygon> = Vec::new();
+    let mut holes: Vec<LineString> = Vec::new();
+
+    for ring in rings {
+        if ring.is_ccw() {
+            holes.push(ring);
+        } else {
+            shells.push(Polygon::new(ring, vec![]));
+        }
+    }
+
+    let mut interiors: Vec<Vec<LineString>> = vec![Vec::new(); shells.len()];
+
+    for hole in holes {
+        let owner = shells
+            .iter()
+            .enumerate()
+            .filter(|(_, shell)| hole.coords().any(|c| shell.contains(c)))
+            .min_by(|(_, a), (_, b)| a.unsigned_area().total_cmp(&b.unsigned_area()))
+            .map(|(i, _)| i);
+
+        match owner {
+            Some(i) => interiors[i].push(hole),
+            None => {
+                shells.push(Polygon::new(hole, vec![]));
+                interiors.push(Vec::new());
+            }
+        }
+    }
+
+    let polys = shells
+        .into_iter()
+        .zip(interiors)
+        .map(|(shell, interiors)| Polygon::new(shell.into_inner().0, interiors))
+        .collect();
+
+    MultiPolygon::new(polys)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a clockwise or counter-clockwise square ring from `lo` to `hi`
+    fn square(lo: f64, hi: f64, cw: bool) -> Value {
+        let mut ring = vec![[lo, lo], [lo, hi], [hi, hi], [hi, lo], [lo, lo]];
+        if !cw {
+            ring.reverse();
+        }
+        json!(ring)
+    }
+
+    #[test]
+    fn holes_of_nested_islands_belong_to_the_island() {
+        let x = json!({
+            "rings": [
+                square(0.0, 10.0, true),
+                square(1.0, 9.0, false),
+                square(2.0, 8.0, true),
+                square(3.0, 7.0, false),
+            ]
+        });
+
+        let res = match esri_to_geom(&x).unwrap().geom {
+            Geometry::MultiPolygon(x) => x,
+            x => panic!("expected a MultiPolygon, found {x:?}"),
+        };
+
+        assert_eq!(res.0.len(), 2);
+        assert_eq!(res.0[0].exterior().0[0], coord! { x: 0.0, y: 0.0 });
+        assert_eq!(res.0[0].interiors().len(), 1);
+        assert_eq!(res.0[0].interiors()[0].0[0], coord! { x: 1.0, y: 1.0 });
+        assert_eq!(res.0[1].exterior().0[0], coord! { x: 2.0, y: 2.0 });
+        assert_eq!(res.0[1].interiors().len(), 1);
+        assert_eq!(res.0[1].interiors()[0].0[0], coord! { x: 3.0, y: 3.0 });
+    }
+
+    #[test]
+    fn z_and_m_values_are_rejected() {
+        let wrong_dims = |found| SfConversionError::WrongDimensions { expected: 2, found };
+
+        let x = json!({ "hasZ": true, "paths": [[[0, 0, 1], [1, 1, 1]]] });
+        assert_eq!(esri_to_geom(&x).unwrap_err(), wrong_dims(3));
+
+        let x = json!({ "x": 1, "y": 2, "z": 3, "m": 4 });
+        assert_eq!(esri_to_geom(&x).unwrap_err(), wrong_dims(4));
+
+        let x = json!({ "points": [[0, 0, 1]] });
+        assert_eq!(esri_to_geom(&x).unwrap_err(), wrong_dims(3));
+    }
+}
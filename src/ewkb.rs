@@ -0,0 +1,295 @@
+//! Read and write PostGIS Extended Well-Known Binary (EWKB)
+//!
+//! EWKB extends WKB with an optional SRID which is flagged in the geometry
+//! type code of the outermost geometry. PostGIS dumps geometries as
+//! hex-encoded EWKB strings e.g. `0101000020E6100000000000000000F03F0000000000000040`
+//! which is `SRID=4326;POINT (1 2)`.
+//!
+//! The SRID is mapped to and from the [`Crs`] of an sfc object. A CRS with an
+//! EPSG code (see [`Crs::epsg()`]) is written as its SRID. When reading, an
+//! SRID of `0` is an unknown CRS as in PostGIS.
+//!
+//! See [`wkb`](crate::wkb) for how geometries are encoded.
+use crate::{
+    crs::Crs,
+    error::SfConversionError,
+    fromsf::sfc_to_geoms_crs,
+    wkb::{ByteOrder, WkbReader, WkbWriter, EWKB_SRID},
+    Geom,
+};
+use extendr_api::prelude::*;
+use std::result::Result;
+
+impl Geom {
+    /// Write the geometry as EWKB with an optional SRID
+    pub fn to_ewkb(&self, srid: Option<i32>, byte_order: ByteOrder) -> Vec<u8> {
+        let mut writer = WkbWriter::new(byte_order);
+        writer.geometry_with_header(&self.geom, |w, typ| match srid {
+            Some(srid) => {
+                w.header(typ | EWKB_SRID);
+                w.u32(srid as u32);
+            }
+            None => w.header(typ),
+        });
+        writer.buf
+    }
+
+    /// Write the geometry as hex-encoded little-endian EWKB with an optional SRID
+    pub fn to_hex_ewkb(&self, srid: Option<i32>) -> String {
+        to_hex(&self.to_ewkb(srid, ByteOrder::LittleEndian))
+    }
+}
+
+/// Read an EWKB geometry returning the `Geom` and its SRID, if any.
+/// Plain WKB is also valid EWKB without an SRID.
+pub fn ewkb_to_geom(x: &[u8]) -> Result<(Geom, Option<i32>), SfConversionError> {
+    let mut reader = WkbReader::new(x);
+    let typ = reader.header()?;
+
+    let srid = if typ & EWKB_SRID != 0 {
+        Some(reader.u32()? as i32)
+    } else {
+        None
+    };
+
+    let typ = WkbReader::xy_type(typ & !EWKB_SRID)?;
+    let geom = reader.geometry_body(typ)?;
+    reader.finish()?;
+
+    Ok((Geom::from(geom), srid))
+}
+
+/// Read a hex-encoded EWKB string returning the `Geom` and its SRID, if any.
+pub fn hex_ewkb_to_geom(x: &str) -> Result<(Geom, Option<i32>), SfConversionError> {
+    ewkb_to_geom(&from_hex(x)?)
+}
+
+/// Parse a character vector of hex-encoded EWKB. `NA` values are `None`.
+///
+/// The CRS is determined from the SRID of the geometries. All geometries
+/// must share the same SRID.
+pub fn hex_ewkb_to_geoms(x: Strings) -> Result<(Vec<Option<Geom>>, Crs), SfConversionError> {
+    let parsed = x
+        .iter()
+        .map(|xi| {
+            if xi.is_na() {
+                Ok(None)
+            } else {
+                hex_ewkb_to_geom(xi.as_str()).map(Some)
+            }
+        })
+        .collect::<Result<Vec<Option<(Geom, Option<i32>)>>, SfConversionError>>()?;
+
+    collect_srid(parsed)
+}
+
+/// Convert a list of EWKB raw vectors into geometries. `NULL` elements are `None`.
+///
+/// The CRS is determined from the SRID of the geometries. All geometries
+/// must share the same SRID.
+pub fn ewkb_to_geoms(x: List) -> Result<(Vec<Option<Geom>>, Crs), SfConversionError> {
+    let parsed = x
+        .into_iter()
+        .map(|(_, xi)| {
+            if xi.is_null() {
+                return Ok(None);
+            }
+
+            let bytes = xi
+                .as_raw_slice()
+                .ok_or_else(|| SfConversionError::bad_storage("raw", &xi))?;
+            ewkb_to_geom(bytes).map(Some)
+        })
+        .collect::<Result<Vec<Option<(Geom, Option<i32>)>>, SfConversionError>>()?;
+
+    collect_srid(parsed)
+}
+
+/// Write geometries as a character vector of hex-encoded EWKB. The SRID is
+/// the EPSG code of the CRS, if it has one. `None` values are `NA`.
+pub fn geoms_to_hex_ewkb(x: &[Option<Geom>], crs: &Crs) -> Strings {
+    let srid = crs.epsg();
+    Strings::from_values(x.iter().map(|xi| match xi {
+        Some(geom) => Rstr::from(geom.to_hex_ewkb(srid)),
+        None => Rstr::na(),
+    }))
+}
+
+/// Create a `WKB` class list of EWKB raw vectors. The SRID is the EPSG code
+/// of the CRS, if it has one. `None` values are `NULL`.
+pub fn geoms_to_ewkb(x: &[Option<Geom>], crs: &Crs, byte_order: ByteOrder) -> Robj {
+    let srid = crs.epsg();
+    let res = List::from_values(x.iter().map(|xi| match xi {
+        Some(geom) => Raw::from_bytes(&geom.to_ewkb(srid, byte_order)).into_robj(),
+        None => ().into_robj(),
+    }));
+
+    res.into_robj().set_class(["WKB"]).unwrap().clone()
+}
+
+/// Write an sfc object as a character vector of hex-encoded EWKB. The sfc CRS
+/// is written as the SRID. `NULL` geometries are `NA`.
+pub fn sfc_to_hex_ewkb(x: List) -> Result<Strings, SfConversionError> {
+    let (geoms, crs) = sfc_to_geoms_crs(x)?;
+    Ok(geoms_to_hex_ewkb(&geoms, &crs))
+}
+
+// separates the SRIDs from the geometries ensuring that they are all the same
+fn collect_srid(
+    x: Vec<Option<(Geom, Option<i32>)>>,
+) -> Result<(Vec<Option<Geom>>, Crs), SfConversionError> {
+    let mut srid: Option<i32> = None;
+    let mut geoms = Vec::with_capacity(x.len());
+
+    for xi in x {
+        match xi {
+            Some((geom, geom_srid)) => {
+                match (srid, geom_srid) {
+                    (Some(a), Some(b)) if a != b => {
+                        return Err(SfConversionError::Parse(format!(
+                            "geometries have different SRIDs: {a} and {b}"
+                        )))
+                    }
+                    (None, Some(b)) => srid = Some(b),
+                    _ => (),
+                }
+                geoms.push(Some(geom));
+            }
+            None => geoms.push(None),
+        }
+    }
+
+    let crs = match srid {
+        Some(srid) if srid != 0 => Crs::from_epsg(srid),
+        _ => Crs::default(),
+    };
+
+    Ok((geoms, crs))
+}
+
+fn to_hex(x: &[u8]) -> String {
+    const HEX: &[u8; 16] = b"0123456789ABCDEF";
+    let mut res = String::with_capacity(x.len() * 2);
+    for byte in x {
+        res.push(HEX[(byte >> 4) as usize] as char);
+        res.push(HEX[(byte & 0x0F) as usize] as char);
+    }
+    res
+}
+
+fn from_hex(x: &str) -> Result<Vec<u8>, SfConversionError> {
+    // PostGIS hex may be prefixed with `\x` when dumped from bytea
+    let x = x.trim();
+    let x = x.strip_prefix("\\x").unwrap_or(x);
+
+    if x.len() % 2 != 0 {
+        return Err(SfConversionError::Parse(
+            "hex string has an odd number of characters".into(),
+        ));
+    }
+
+    (0..x.len())
+        .step_by(2)
+        .map(|i| {
+            // from_str_radix() accepts a leading `+` so check the digits first
+            x.get(i..i + 2)
+                .filter(|b| b.bytes().all(|d| d.is_ascii_hexdigit()))
+                .and_then(|b| u8::from_str_radix(b, 16).ok())
+                .ok_or_else(|| SfConversionError::Parse(format!("invalid hex at position {i}")))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wkt::wkt_to_geom;
+
+    const POINT_4326: &str = "0101000020E6100000000000000000F03F0000000000000040";
+
+    // writes WKT as hex EWKB with an SRID and reads it back
+    fn round_trip(x: &str, srid: Option<i32>) {
+        let hex = wkt_to_geom(x).unwrap().to_hex_ewkb(srid);
+        let (geom, res_srid) = hex_ewkb_to_geom(&hex).unwrap();
+        assert_eq!(geom.to_wkt(), x);
+        assert_eq!(res_srid, srid);
+    }
+
+    #[test]
+    fn points_match_postgis() {
+        let geom = wkt_to_geom("POINT (1 2)").unwrap();
+        assert_eq!(geom.to_hex_ewkb(Some(4326)), POINT_4326);
+
+        let (geom, srid) = hex_ewkb_to_geom(POINT_4326).unwrap();
+        assert_eq!(geom.to_wkt(), "POINT (1 2)");
+        assert_eq!(srid, Some(4326));
+
+        // bytea dumps are lower case and prefixed with `\x`
+        let bytea = format!("\\x{}", POINT_4326.to_lowercase());
+        assert_eq!(hex_ewkb_to_geom(&bytea).unwrap().1, Some(4326));
+    }
+
+    #[test]
+    fn geometries_round_trip() {
+        for srid in [None, Some(0), Some(3857)] {
+            round_trip("POINT (1 2)", srid);
+            round_trip("LINESTRING (0 0, 1.5 -2)", srid);
+            round_trip("POLYGON ((0 0, 1 0, 1 1, 0 0))", srid);
+            round_trip("MULTIPOLYGON (((0 0, 1 0, 1 1, 0 0)), EMPTY)", srid);
+            round_trip(
+                "GEOMETRYCOLLECTION (POINT (1 2), MULTIPOINT ((1 2), EMPTY))",
+                srid,
+            );
+        }
+    }
+
+    #[test]
+    fn empties_round_trip() {
+        round_trip("POINT EMPTY", Some(4326));
+        round_trip("LINESTRING EMPTY", Some(4326));
+        round_trip("POLYGON EMPTY", None);
+        round_trip("GEOMETRYCOLLECTION EMPTY", Some(4326));
+    }
+
+    #[test]
+    fn plain_wkb_has_no_srid() {
+        let wkb = wkt_to_geom("POINT (1 2)")
+            .unwrap()
+            .to_wkb(ByteOrder::BigEndian);
+        let (geom, srid) = ewkb_to_geom(&wkb).unwrap();
+        assert_eq!(geom.to_wkt(), "POINT (1 2)");
+        assert_eq!(srid, None);
+    }
+
+    #[test]
+    fn malformed_hex_is_an_error() {
+        let malformed = [
+            // odd number of characters
+            &POINT_4326[1..],
+            // `from_str_radix()` accepts a sign
+            "+f",
+            "0101000020E6100000000000000000F03F00000000000000ZZ",
+            // truncated
+            &POINT_4326[..POINT_4326.len() - 2],
+            // trailing bytes
+            "0101000020E6100000000000000000F03F000000000000004000",
+            "",
+        ];
+
+        for x in malformed {
+            assert!(
+                matches!(hex_ewkb_to_geom(x), Err(SfConversionError::Parse(_))),
+                "{x}"
+            );
+        }
+
+        // Z flag
+        assert!(matches!(
+            hex_ewkb_to_geom("01010000A0E6100000000000000000F03F00000000000000400000000000000840"),
+            Err(SfConversionError::WrongDimensions {
+                expected: 2,
+                found: 3
+            })
+        ));
+    }
+}
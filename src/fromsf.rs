@@ -22,24 +22,23 @@
 
 use extendr_api::prelude::*;
 
-use crate::{vctrs::determine_geoms_class, Geom};
-use geo_types::Geometry;
+use crate::{
+    constructors::*,
+    crs::Crs,
+    error::SfConversionError,
+    vctrs::geoms_to_rsgeo,
+    zm::{Dim, GeomZM, OrdinateReader},
+    Geom,
+};
+use geo_types::{
+    Geometry, GeometryCollection, LineString, MultiLineString, MultiPoint, MultiPolygon, Point,
+    Polygon,
+};
 
-use std::{error::Error, result::Result};
+use std::result::Result;
 
-pub fn sfc_to_rsgeo(x: List) -> Robj {
-    let mut rsgeo = List::new(x.len());
-
-    for (i, (_, obj)) in x.iter().enumerate() {
-        rsgeo.set_elt(i, sfg_to_rsgeo(obj)).unwrap();
-    }
-
-    // see https://github.com/extendr/extendr/pull/540
-    // let rsgeo = x
-    //     .into_iter()
-    //     .map(|(_, robj)| sfg_to_rsgeo(robj)).collect::<List>();
-    let cls = determine_geoms_class(&rsgeo);
-    rsgeo.set_class(cls).unwrap().clone().into()
+pub fn sfc_to_rsgeo(x: List) -> Result<Robj, SfConversionError> {
+    Ok(geoms_to_rsgeo(sfc_to_geoms(x)?))
 }
 
 // These functions are for people who do not want to use rsgeo
@@ -47,120 +46,308 @@ pub fn sfc_to_rsgeo(x: List) -> Robj {
 /// Given an sfc object, creates a vector of `Option<Geometry>`. NULL geometries are stored
 /// as `None` and non-null are `Some(Geometry)`. Match on the result to get the underlying
 /// geo-types geometry object or handle null geometry.
-pub fn sfc_to_geometry(x: List) -> Vec<Option<Geometry>> {
+///
+/// Empty geometries are not missing and are returned as `Some`. Use
+/// [`geometry_is_empty()`](crate::geometry_is_empty) to identify them.
+pub fn sfc_to_geometry(x: List) -> Result<Vec<Option<Geometry>>, SfConversionError> {
+    let res = sfc_to_geoms(x)?
+        .into_iter()
+        .map(|geo| geo.map(|g| g.geom))
+        .collect::<Vec<Option<Geometry>>>();
+
+    Ok(res)
+}
+
+/// Given an sfc object, creates a vector of `Option<Geom>`. NULL geometries are `None`
+/// whereas empty geometries are `Some`. Returns an error if any sfg cannot be converted.
+pub fn sfc_to_geoms(x: List) -> Result<Vec<Option<Geom>>, SfConversionError> {
     x.into_iter()
         .map(|(_, robj)| {
-            let geo = sfg_to_geom(robj);
-            match geo {
-                Ok(g) => Some(g.geom),
-                Err(_) => None,
+            if robj.is_null() {
+                Ok(None)
+            } else {
+                sfg_to_geom(robj).map(Some)
             }
         })
-        .collect::<Vec<Option<Geometry>>>()
+        .collect::<Result<Vec<Option<Geom>>, SfConversionError>>()
 }
 
-pub fn sfc_to_geoms(x: List) -> Vec<Option<Geom>> {
-    x.into_iter()
-        .map(|(_, robj)| {
-            let geo = sfg_to_geom(robj);
-            match geo {
-                Ok(g) => Some(g),
-                Err(_) => None,
-            }
+/// Extracts the coordinate reference system from the `crs` attribute of an sfc object.
+/// If the attribute is missing or malformed an unknown (`NA`) `Crs` is returned.
+pub fn sfc_crs(x: &List) -> Crs {
+    x.get_attrib("crs")
+        .and_then(|crs| Crs::try_from(crs).ok())
+        .unwrap_or_default()
+}
+
+/// Like `sfc_to_geometry()` but also returns the `Crs` of the sfc object.
+pub fn sfc_to_geometry_crs(x: List) -> Result<(Vec<Option<Geometry>>, Crs), SfConversionError> {
+    let crs = sfc_crs(&x);
+    Ok((sfc_to_geometry(x)?, crs))
+}
+
+/// Like `sfc_to_geoms()` but also returns the `Crs` of the sfc object. The `Crs`
+/// can be reattached to the result with [`to_sfc_with_crs()`](crate::tosf::to_sfc_with_crs).
+pub fn sfc_to_geoms_crs(x: List) -> Result<(Vec<Option<Geom>>, Crs), SfConversionError> {
+    let crs = sfc_crs(&x);
+    Ok((sfc_to_geoms(x)?, crs))
+}
+
+/// The geometries and attributes of an sf data.frame.
+///
+/// Only the active geometry column, named by `sf_column`, is converted.
+/// Any other columns, including additional sfc columns, are kept as is.
+#[derive(Debug, Clone)]
+pub struct SfDataFrame {
+    /// the geometries of the active geometry column
+    pub geoms: Vec<Option<Geom>>,
+    /// the CRS of the active geometry column
+    pub crs: Crs,
+    /// the name of the active geometry column
+    pub sf_column: String,
+    /// the non-geometry columns in their original order
+    pub columns: Vec<(String, Robj)>,
+    /// the attribute-geometry relationship of each column, `"constant"`,
+    /// `"aggregate"`, or `"identity"`. `None` is `NA`.
+    pub agr: Vec<Option<String>>,
+}
+
+impl SfDataFrame {
+    /// Create an `SfDataFrame` with the geometry column `"geometry"`, an
+    /// unknown CRS, and an `NA` attribute-geometry relationship for each column.
+    pub fn new(geoms: Vec<Option<Geom>>, columns: Vec<(String, Robj)>) -> Self {
+        let agr = vec![None; columns.len()];
+        SfDataFrame {
+            geoms,
+            crs: Crs::default(),
+            sf_column: String::from("geometry"),
+            columns,
+            agr,
+        }
+    }
+
+    /// Set the CRS of the geometry column
+    pub fn with_crs(mut self, crs: Crs) -> Self {
+        self.crs = crs;
+        self
+    }
+}
+
+/// Convert an sf data.frame into its geometries, CRS, and attribute columns.
+/// The `sf_column` and `agr` attributes are retained. Use
+/// [`to_sf()`](crate::tosf::to_sf) to create an sf data.frame again.
+pub fn sf_to_data_frame(x: List) -> Result<SfDataFrame, SfConversionError> {
+    if !x.inherits("sf") {
+        return Err(SfConversionError::MissingClass("sf"));
+    }
+
+    let sf_column = x
+        .get_attrib("sf_column")
+        .and_then(|col| col.as_str().map(String::from))
+        .ok_or(SfConversionError::MissingAttribute("sf_column"))?;
+
+    let mut geometry = None;
+    let mut columns = Vec::with_capacity(x.len().saturating_sub(1));
+
+    for (name, col) in x.iter() {
+        if name == sf_column {
+            geometry = Some(as_list(col)?);
+        } else {
+            columns.push((name.to_string(), col));
+        }
+    }
+
+    let geometry = geometry.ok_or_else(|| SfConversionError::MissingField(sf_column.clone()))?;
+    let agr = sf_agr(&x, &columns);
+    let (geoms, crs) = sfc_to_geoms_crs(geometry)?;
+
+    Ok(SfDataFrame {
+        geoms,
+        crs,
+        sf_column,
+        columns,
+        agr,
+    })
+}
+
+// Reads the `agr` factor of an sf data.frame for each attribute column.
+// Columns missing from the `agr` attribute are `None`.
+fn sf_agr(x: &List, columns: &[(String, Robj)]) -> Vec<Option<String>> {
+    let agr = x.get_attrib("agr");
+    let levels = agr
+        .as_ref()
+        .and_then(|agr| agr.get_attrib("levels"))
+        .and_then(|lvls| Strings::try_from(lvls).ok());
+    let names = agr
+        .as_ref()
+        .and_then(|agr| agr.names())
+        .map(|nms| nms.map(String::from).collect::<Vec<String>>());
+    let codes = agr.and_then(|agr| Integers::try_from(agr).ok());
+
+    let (levels, names, codes) = match (levels, names, codes) {
+        (Some(levels), Some(names), Some(codes)) => (levels, names, codes),
+        _ => return vec![None; columns.len()],
+    };
+
+    columns
+        .iter()
+        .map(|(name, _)| {
+            let i = names.iter().position(|nm| nm == name)?;
+            factor_level(&levels, codes.elt(i))
         })
-        .collect::<Vec<Option<Geom>>>()
+        .collect()
+}
+
+// The level of a factor code. `NA` codes and codes without a level are `None`.
+pub(crate) fn factor_level(levels: &Strings, code: Rint) -> Option<String> {
+    if code.is_na() || code.inner() < 1 || code.inner() as usize > levels.len() {
+        return None;
+    }
+
+    let lvl = levels.elt(code.inner() as usize - 1);
+    (!lvl.is_na()).then(|| lvl.as_str().to_string())
+}
+
+// Extracts the class of an sfg object e.g. `["XY", "POINT", "sfg"]`
+fn sfg_class(x: &Robj) -> Result<Vec<&'static str>, SfConversionError> {
+    let cls = x
+        .class()
+        .ok_or(SfConversionError::MissingClass("sfg"))?
+        .collect::<Vec<&str>>();
+
+    if cls.len() < 3 || cls[2] != "sfg" {
+        return Err(SfConversionError::MissingClass("sfg"));
+    }
+
+    Ok(cls)
 }
 
 /// Falliably takes an extendr `Robj` and returns a `Geom` struct.
 /// Supports conversion from `"POINT"`, `"MULTIPOINT"`, `"LINESTRING"`, `"MULTILINESTRING"`,
-/// `"POLYGON"`, and `"MULTIPOLYGON"` to their corresponding geo_type primitive.
-// `GEOMETRYCOLLECTION` are not supported.
+/// `"POLYGON"`, `"MULTIPOLYGON"`, and `"GEOMETRYCOLLECTION"` to their corresponding
+/// geo_type primitive. Geometry collections are converted recursively so nested
+/// collections are preserved.
+///
+/// Empty geometries are supported. `POINT EMPTY` is a `Point` with `NaN` coordinates.
+///
+/// Only `"XY"` geometries are supported. Geometries with Z or M dimensions return
+/// an error; use [`sfg_to_geom_zm()`] to retain them or drop them explicitly with
+/// [`GeomZM::drop_zm()`].
 ///
 /// ```
 /// use extendr_api::prelude::*;
 /// use extendr_api::Doubles;
-/// use sfconversions::sfg_to_geometry;
+/// use sfconversions::fromsf::sfg_to_geom;
 /// // Create an extendr doubles object and set the appropriate class
 /// let dbls = Doubles::from_values([0.0, 10.0])
 ///     .into_robj()
 ///     .set_class(["XY", "POINT", "sfg"])
-///     .unwrap();
+///     .unwrap()
+///     .clone();
 ///
-/// // convert using `sfg_to_geometry()` and extract the underlyig
-/// let geo_primitive = sfg_to_geometry(dbls).geom;
+/// // convert using `sfg_to_geom()` and extract the underlying geometry
+/// let geo_primitive = sfg_to_geom(dbls).unwrap().geom;
 /// ```
 ///
-pub fn sfg_to_geom(x: Robj) -> Result<Geom, Box<dyn Error>> {
-    let cls2 = x.class().unwrap().map(|x| x).collect::<Vec<&str>>();
-    let cls = cls2[1];
-
-    match cls {
-        "POINT" => {
-            let x = Doubles::try_from(x).unwrap();
-            Ok(geom_point(x[0].inner(), x[1].inner()).into())
-        }
-        "MULTIPOINT" => {
-            let x = RMatrix::try_from(&x).unwrap();
-            Ok(geom_multipoint(x).into())
-        }
-        "LINESTRING" => {
-            let x = RMatrix::try_from(&x).unwrap();
-            Ok(geom_linestring(x).into())
-        }
+pub fn sfg_to_geom(x: Robj) -> Result<Geom, SfConversionError> {
+    let cls2 = sfg_class(&x)?;
 
-        "MULTILINESTRING" => {
-            let x = List::try_from(x).unwrap();
-            Ok(geom_multilinestring(x).into())
-        }
-        "POLYGON" => {
-            let x = List::try_from(x).unwrap();
-            Ok(geom_polygon(x).into())
-        }
+    if cls2[0] != "XY" {
+        let found = Dim::from_class(cls2[0]).map_or(0, |d| d.n_dims());
+        return Err(SfConversionError::WrongDimensions { expected: 2, found });
+    }
 
-        "MULTIPOLYGON" => {
-            let x = List::try_from(x).unwrap();
-            Ok(geom_multipolygon(x).into())
-        }
+    let res: Geometry = match cls2[1] {
+        "POINT" => point_inner(as_doubles(x)?)?.into(),
+        "MULTIPOINT" => MultiPoint::new(matrix_to_points(as_matrix(x)?)?).into(),
+        "LINESTRING" => LineString::new(matrix_to_coords(as_matrix(x)?)?).into(),
+        "MULTILINESTRING" => multilinestring_inner(as_list(x)?)?.into(),
+        "POLYGON" => polygon_inner(as_list(x)?)?.into(),
+        "MULTIPOLYGON" => multipolygon_inner(as_list(x)?)?.into(),
+        "GEOMETRYCOLLECTION" => geometrycollection_inner(as_list(x)?)?.into(),
+        cls => return Err(SfConversionError::UnsupportedType(cls.to_string())),
+    };
+
+    Ok(Geom::from(res))
+}
 
-        &_ => Err(format!("Null or unsupported geometry type").into()),
+/// Convert an sfg object to a `Geom` pointer with the appropriate class.
+/// `NULL` is returned as is.
+#[extendr]
+pub fn sfg_to_rsgeo(x: Robj) -> extendr_api::Result<Robj> {
+    if x.is_null() {
+        return Ok(x);
     }
+
+    Ok(into_geom_robj(sfg_to_geom(x)?.geom))
 }
 
-use crate::constructors::*;
+/// Falliably takes an sfg `Robj` with any coordinate dimension and returns a `GeomZM`.
+/// The X and Y ordinates are stored in the `Geom` whereas the Z and M ordinates
+/// are carried alongside it. Supports the same geometry types as [`sfg_to_geom()`].
+pub fn sfg_to_geom_zm(x: Robj) -> Result<GeomZM, SfConversionError> {
+    let cls = sfg_class(&x)?;
+    let dim = Dim::from_class(cls[0]).ok_or(SfConversionError::MissingClass("XY"))?;
+    let mut reader = OrdinateReader::new(dim);
+    let geom = sfg_to_geometry_zm(x, &mut reader)?;
 
-#[extendr]
-pub fn sfg_to_rsgeo(x: Robj) -> Robj {
-    let cls2 = x.class().unwrap().map(|x| x).collect::<Vec<&str>>();
-    let cls = cls2[1];
-
-    match cls {
-        "POINT" => {
-            let x = Doubles::try_from(x).unwrap();
-            geom_point(x[0].inner(), x[1].inner())
-        }
-        "MULTIPOINT" => {
-            let x = RMatrix::try_from(&x).unwrap();
-            geom_multipoint(x)
-        }
-        "LINESTRING" => {
-            let x = RMatrix::try_from(&x).unwrap();
-            geom_linestring(x)
-        }
+    GeomZM::new(Geom::from(geom), dim, reader.z, reader.m)
+}
 
+// recursively reads sfg objects collecting their Z and M ordinates
+fn sfg_to_geometry_zm(x: Robj, reader: &mut OrdinateReader) -> Result<Geometry, SfConversionError> {
+    let cls2 = sfg_class(&x)?;
+
+    // all members of a geometry collection must share the same dimension
+    let dim = Dim::from_class(cls2[0]).ok_or(SfConversionError::MissingClass("XY"))?;
+    if dim != reader.dim {
+        return Err(SfConversionError::WrongDimensions {
+            expected: reader.dim.n_dims(),
+            found: dim.n_dims(),
+        });
+    }
+
+    let res: Geometry = match cls2[1] {
+        "POINT" => Point::from(reader.point(as_doubles(x)?)?).into(),
+        "MULTIPOINT" => MultiPoint::from(reader.coords(as_matrix(x)?)?).into(),
+        "LINESTRING" => LineString::new(reader.coords(as_matrix(x)?)?).into(),
         "MULTILINESTRING" => {
-            let x = List::try_from(x).unwrap();
-            geom_multilinestring(x)
-        }
-        "POLYGON" => {
-            let x = List::try_from(x).unwrap();
-            geom_polygon(x)
+            let lns = as_list(x)?
+                .into_iter()
+                .map(|(_, xi)| Ok(LineString::new(reader.coords(as_matrix(xi)?)?)))
+                .collect::<Result<Vec<LineString>, SfConversionError>>()?;
+            MultiLineString::new(lns).into()
         }
-
+        "POLYGON" => polygon_zm(as_list(x)?, reader)?.into(),
         "MULTIPOLYGON" => {
-            let x = List::try_from(x).unwrap();
-            geom_multipolygon(x)
+            let polys = as_list(x)?
+                .into_iter()
+                .map(|(_, xi)| polygon_zm(as_list(xi)?, reader))
+                .collect::<Result<Vec<Polygon>, SfConversionError>>()?;
+            MultiPolygon::new(polys).into()
         }
+        "GEOMETRYCOLLECTION" => {
+            let geoms = as_list(x)?
+                .into_iter()
+                .map(|(_, xi)| sfg_to_geometry_zm(xi, reader))
+                .collect::<Result<Vec<Geometry>, SfConversionError>>()?;
+            GeometryCollection::new_from(geoms).into()
+        }
+        cls => return Err(SfConversionError::UnsupportedType(cls.to_string())),
+    };
 
-        &_ => Robj::from(NULL),
-    }
+    Ok(res)
+}
+
+fn polygon_zm(x: List, reader: &mut OrdinateReader) -> Result<Polygon, SfConversionError> {
+    let rings = x
+        .into_iter()
+        .map(|(_, xi)| {
+            let coords = reader.coords(as_matrix(xi)?)?;
+            validate_ring(&coords)?;
+            Ok(LineString::new(coords))
+        })
+        .collect::<Result<Vec<LineString>, SfConversionError>>()?;
+
+    Ok(polygon_from_rings(rings))
 }
@@ -0,0 +1,917 @@
+//! Exchange geometry columns with Arrow through the C Data Interface
+//!
+//! Geometries are exported as [geoarrow](https://geoarrow.org) extension
+//! arrays using the [Arrow C Data Interface](https://arrow.apache.org/docs/format/CDataInterface.html).
+//! This is the columnar sibling of [`sfc_to_geometry()`](crate::fromsf::sfc_to_geometry)
+//! and [`to_sfc()`](crate::tosf::to_sfc) and lets whole geometry columns be
+//! handed to nanoarrow, arrow, and DuckDB without going through sf.
+//!
+//! Two encodings are supported:
+//!
+//! - native: `geoarrow.point`, `geoarrow.linestring`, `geoarrow.polygon`,
+//!   `geoarrow.multipoint`, `geoarrow.multilinestring`, and `geoarrow.multipolygon`
+//!   with separated `x` and `y` coordinate buffers and `int32` offsets.
+//! - `geoarrow.wkb`: a binary array of WKB.
+//!
+//! A native array can only hold a single geometry type. `Line` is written as a
+//! linestring whereas `Rect` and `Triangle` are polygons. Use
+//! [`GeoArrowEncoding::Wkb`] for mixed geometry types and geometry collections.
+//!
+//! When importing, both separated and interleaved coordinates are supported as
+//! are `binary` and `large_binary` WKB. Missing geometries are null. An empty
+//! point is written with NaN coordinates.
+//!
+//! In R, structures are allocated with `nanoarrow::nanoarrow_allocate_schema()`
+//! and `nanoarrow::nanoarrow_allocate_array()`. Their addresses from
+//! `nanoarrow::nanoarrow_pointer_addr_chr()` can be passed to Rust and read
+//! with [`pointer_from_address()`].
+use crate::{
+    constructors::{polygon_from_rings, validate_ring},
+    crs::Crs,
+    error::SfConversionError,
+    fromsf::sfc_to_geoms_crs,
+    tosf::{sfg_type, to_sfc_with_crs},
+    wkb::{wkb_to_geom, ByteOrder},
+    Geom,
+};
+use extendr_api::prelude::*;
+use geo_types::{
+    Coord, Geometry, LineString, MultiLineString, MultiPoint, MultiPolygon, Point, Polygon,
+};
+use serde_json::{json, Value};
+use std::ffi::{c_char, c_void, CStr, CString};
+use std::result::Result;
+
+/// The encoding used when exporting geometries
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum GeoArrowEncoding {
+    /// geoarrow native encodings with separated coordinates
+    #[default]
+    Native,
+    /// `geoarrow.wkb`
+    Wkb,
+}
+
+/// The `ArrowSchema` structure of the Arrow C Data Interface
+#[repr(C)]
+#[derive(Debug)]
+pub struct ArrowSchema {
+    pub format: *const c_char,
+    pub name: *const c_char,
+    pub metadata: *const c_char,
+    pub flags: i64,
+    pub n_children: i64,
+    pub children: *mut *mut ArrowSchema,
+    pub dictionary: *mut ArrowSchema,
+    pub release: Option<unsafe extern "C" fn(*mut ArrowSchema)>,
+    pub private_data: *mut c_void,
+}
+
+/// The `ArrowArray` structure of the Arrow C Data Interface
+#[repr(C)]
+#[derive(Debug)]
+pub struct ArrowArray {
+    pub length: i64,
+    pub null_count: i64,
+    pub offset: i64,
+    pub n_buffers: i64,
+    pub n_children: i64,
+    pub buffers: *mut *const c_void,
+    pub children: *mut *mut ArrowArray,
+    pub dictionary: *mut ArrowArray,
+    pub release: Option<unsafe extern "C" fn(*mut ArrowArray)>,
+    pub private_data: *mut c_void,
+}
+
+/// Structures owned by Rust are released when dropped
+impl Drop for ArrowSchema {
+    fn drop(&mut self) {
+        if let Some(release) = self.release {
+            unsafe { release(self) }
+        }
+    }
+}
+
+/// Structures owned by Rust are released when dropped
+impl Drop for ArrowArray {
+    fn drop(&mut self) {
+        if let Some(release) = self.release {
+            unsafe { release(self) }
+        }
+    }
+}
+
+// flag indicating that a field is nullable
+const ARROW_FLAG_NULLABLE: i64 = 2;
+
+/// Export geometries as a geoarrow array. The CRS is written to the
+/// extension metadata.
+pub fn geoms_to_arrow(
+    x: &[Option<Geom>],
+    crs: &Crs,
+    encoding: GeoArrowEncoding,
+) -> Result<(ArrowSchema, ArrowArray), SfConversionError> {
+    let metadata = extension_metadata(crs);
+    let node = match encoding {
+        GeoArrowEncoding::Wkb => wkb_node(x, metadata),
+        GeoArrowEncoding::Native => {
+            let mut builder = NativeBuilder::new(native_type(x)?);
+            for xi in x {
+                builder.push(xi.as_ref().map(|g| &g.geom))?;
+            }
+            builder.finish(metadata)
+        }
+    };
+
+    Ok(export_node(node))
+}
+
+/// Export an sfc object as a geoarrow array. The sfc CRS is written to the
+/// extension metadata.
+pub fn sfc_to_arrow(
+    x: List,
+    encoding: GeoArrowEncoding,
+) -> Result<(ArrowSchema, ArrowArray), SfConversionError> {
+    let (geoms, crs) = sfc_to_geoms_crs(x)?;
+    geoms_to_arrow(&geoms, &crs, encoding)
+}
+
+/// Export geometries into Arrow C Data Interface structures allocated by the
+/// caller such as those from `nanoarrow::nanoarrow_allocate_schema()` and
+/// `nanoarrow::nanoarrow_allocate_array()`. Ownership is moved to the caller.
+///
+/// # Safety
+///
+/// `schema` and `array` must be valid pointers to released structures.
+pub unsafe fn export_geoms(
+    x: &[Option<Geom>],
+    crs: &Crs,
+    encoding: GeoArrowEncoding,
+    schema: *mut ArrowSchema,
+    array: *mut ArrowArray,
+) -> Result<(), SfConversionError> {
+    let (exported_schema, exported_array) = geoms_to_arrow(x, crs, encoding)?;
+    std::ptr::write(schema, exported_schema);
+    std::ptr::write(array, exported_array);
+    Ok(())
+}
+
+/// Import a geoarrow array returning the geometries and the CRS from the
+/// extension metadata. Null elements are `None`. The structures are only
+/// read; ownership remains with the caller.
+///
+/// # Safety
+///
+/// `schema` and `array` must be valid, unreleased structures describing the
+/// same array.
+pub unsafe fn arrow_to_geoms(
+    schema: &ArrowSchema,
+    array: &ArrowArray,
+) -> Result<(Vec<Option<Geom>>, Crs), SfConversionError> {
+    let view = ArrowView { schema, array };
+    let (name, crs) = parse_extension(schema);
+
+    let typ = match name.as_deref() {
+        Some(name) => GeoArrowType::from_extension_name(name)
+            .ok_or_else(|| SfConversionError::UnsupportedType(name.to_string()))?,
+        None if matches!(view.format(), "z" | "Z") => GeoArrowType::Wkb,
+        None => {
+            return Err(SfConversionError::Parse(
+                "array is not a geoarrow extension array".into(),
+            ))
+        }
+    };
+
+    let geoms = (0..array.length as usize)
+        .map(|i| {
+            if !view.is_valid(i) {
+                return Ok(None);
+            }
+
+            let geom: Geometry = match typ {
+                GeoArrowType::Point => Point::from(view.coord(i)?).into(),
+                GeoArrowType::LineString => read_linestring(view, i)?.into(),
+                GeoArrowType::Polygon => read_polygon(view, i)?.into(),
+                GeoArrowType::MultiPoint => {
+                    let (start, end) = view.offsets(i)?;
+                    let coords = read_coords(view.child(0)?, start, end)?;
+                    MultiPoint::new(coords.into_iter().map(Point::from).collect()).into()
+                }
+                GeoArrowType::MultiLineString => {
+                    MultiLineString::new(read_multi(view, i, read_linestring)?).into()
+                }
+                GeoArrowType::MultiPolygon => {
+                    MultiPolygon::new(read_multi(view, i, read_polygon)?).into()
+                }
+                GeoArrowType::Wkb => return wkb_to_geom(view.binary(i)?).map(Some),
+            };
+
+            Ok(Some(Geom::from(geom)))
+        })
+        .collect::<Result<Vec<Option<Geom>>, SfConversionError>>()?;
+
+    Ok((geoms, crs))
+}
+
+/// Import a geoarrow array as an sfc object. See [`arrow_to_geoms()`].
+///
+/// # Safety
+///
+/// `schema` and `array` must be valid, unreleased structures describing the
+/// same array.
+pub unsafe fn arrow_to_sfc(
+    schema: &ArrowSchema,
+    array: &ArrowArray,
+) -> Result<Robj, SfConversionError> {
+    let (geoms, crs) = arrow_to_geoms(schema, array)?;
+    Ok(to_sfc_with_crs(geoms, &crs))
+}
+
+/// Parse a pointer address such as the result of
+/// `nanoarrow::nanoarrow_pointer_addr_chr()`.
+pub fn pointer_from_address<T>(x: &str) -> Result<*mut T, SfConversionError> {
+    let addr = x
+        .trim()
+        .parse::<usize>()
+        .map_err(|_| SfConversionError::Parse(format!("invalid pointer address `{x}`")))?;
+
+    if addr == 0 {
+        return Err(SfConversionError::Parse("pointer address is null".into()));
+    }
+
+    Ok(addr as *mut T)
+}
+
+// geoarrow extension types --------------------------------------------------
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum GeoArrowType {
+    Point,
+    LineString,
+    Polygon,
+    MultiPoint,
+    MultiLineString,
+    MultiPolygon,
+    Wkb,
+}
+
+impl GeoArrowType {
+    fn extension_name(&self) -> &'static str {
+        match self {
+            GeoArrowType::Point => "geoarrow.point",
+            GeoArrowType::LineString => "geoarrow.linestring",
+            GeoArrowType::Polygon => "geoarrow.polygon",
+            GeoArrowType::MultiPoint => "geoarrow.multipoint",
+            GeoArrowType::MultiLineString => "geoarrow.multilinestring",
+            GeoArrowType::MultiPolygon => "geoarrow.multipolygon",
+            GeoArrowType::Wkb => "geoarrow.wkb",
+        }
+    }
+
+    fn from_extension_name(x: &str) -> Option<Self> {
+        match x {
+            "geoarrow.point" => Some(GeoArrowType::Point),
+            "geoarrow.linestring" => Some(GeoArrowType::LineString),
+            "geoarrow.polygon" => Some(GeoArrowType::Polygon),
+            "geoarrow.multipoint" => Some(GeoArrowType::MultiPoint),
+            "geoarrow.multilinestring" => Some(GeoArrowType::MultiLineString),
+            "geoarrow.multipolygon" => Some(GeoArrowType::MultiPolygon),
+            "geoarrow.wkb" => Some(GeoArrowType::Wkb),
+            _ => None,
+        }
+    }
+
+    // the names of the nested list fields from outermost to innermost
+    fn list_names(&self) -> &'static [&'static str] {
+        match self {
+            GeoArrowType::Point | GeoArrowType::Wkb => &[],
+            GeoArrowType::LineString => &["vertices"],
+            GeoArrowType::Polygon => &["rings", "vertices"],
+            GeoArrowType::MultiPoint => &["points"],
+            GeoArrowType::MultiLineString => &["linestrings", "vertices"],
+            GeoArrowType::MultiPolygon => &["polygons", "rings", "vertices"],
+        }
+    }
+}
+
+// the native type of a vector of geometries which must all be the same type
+fn native_type(x: &[Option<Geom>]) -> Result<GeoArrowType, SfConversionError> {
+    let mut types = x.iter().flatten().map(|g| sfg_type(&g.geom));
+    let first = types.next().unwrap_or("POINT");
+
+    if types.any(|typ| typ != first) {
+        return Err(SfConversionError::UnsupportedType(String::from("GEOMETRY")));
+    }
+
+    match first {
+        "POINT" => Ok(GeoArrowType::Point),
+        "LINESTRING" => Ok(GeoArrowType::LineString),
+        "POLYGON" => Ok(GeoArrowType::Polygon),
+        "MULTIPOINT" => Ok(GeoArrowType::MultiPoint),
+        "MULTILINESTRING" => Ok(GeoArrowType::MultiLineString),
+        "MULTIPOLYGON" => Ok(GeoArrowType::MultiPolygon),
+        typ => Err(SfConversionError::UnsupportedType(typ.to_string())),
+    }
+}
+
+// The extension metadata records the CRS. EPSG codes are written as an
+// authority code otherwise the WKT is used.
+fn extension_metadata(crs: &Crs) -> String {
+    let metadata = match (crs.epsg(), &crs.wkt, &crs.input) {
+        (Some(code), _, _) => {
+            json!({ "crs": format!("EPSG:{code}"), "crs_type": "authority_code" })
+        }
+        (None, Some(wkt), _) => json!({ "crs": wkt, "crs_type": "wkt2:2019" }),
+        (None, None, Some(input)) => json!({ "crs": input }),
+        (None, None, None) => json!({}),
+    };
+    metadata.to_string()
+}
+
+// Encodes schema metadata: the number of pairs followed by the length
+// prefixed key and value of each pair as native endian int32s
+fn encode_metadata(pairs: &[(&str, &str)]) -> Vec<u8> {
+    let mut res = Vec::new();
+    res.extend_from_slice(&(pairs.len() as i32).to_ne_bytes());
+    for (key, value) in pairs {
+        res.extend_from_slice(&(key.len() as i32).to_ne_bytes());
+        res.extend_from_slice(key.as_bytes());
+        res.extend_from_slice(&(value.len() as i32).to_ne_bytes());
+        res.extend_from_slice(value.as_bytes());
+    }
+    res
+}
+
+// Reads the extension name and the CRS from the schema metadata
+unsafe fn parse_extension(schema: &ArrowSchema) -> (Option<String>, Crs) {
+    let mut name = None;
+    let mut crs = Crs::default();
+
+    if schema.metadata.is_null() {
+        return (name, crs);
+    }
+
+    let mut ptr = schema.metadata as *const u8;
+    let read_i32 = |ptr: &mut *const u8| {
+        let val = std::ptr::read_unaligned(*ptr as *const i32);
+        *ptr = ptr.add(4);
+        val
+    };
+    let read_str = |ptr: &mut *const u8, len: usize| {
+        let bytes = std::slice::from_raw_parts(*ptr, len);
+        *ptr = ptr.add(len);
+        String::from_utf8_lossy(bytes).into_owned()
+    };
+
+    let n = read_i32(&mut ptr);
+    for _ in 0..n {
+        let key_len = read_i32(&mut ptr) as usize;
+        let key = read_str(&mut ptr, key_len);
+        let value_len = read_i32(&mut ptr) as usize;
+        let value = read_str(&mut ptr, value_len);
+
+        match key.as_str() {
+            "ARROW:extension:name" => name = Some(value),
+            "ARROW:extension:metadata" => crs = metadata_crs(&value),
+            _ => (),
+        }
+    }
+
+    (name, crs)
+}
+
+// An authority code is the CRS input. WKT is the CRS wkt and PROJJSON is
+// kept as input as it is accepted by `sf::st_crs()`.
+fn metadata_crs(x: &str) -> Crs {
+    let metadata: Value = match serde_json::from_str(x) {
+        Ok(metadata) => metadata,
+        Err(_) => return Crs::default(),
+    };
+
+    match metadata.get("crs") {
+        Some(Value::String(crs)) => match metadata.get("crs_type").and_then(Value::as_str) {
+            Some("wkt2:2019") => Crs::new(None, Some(crs.clone())),
+            _ => Crs::new(Some(crs.clone()), None),
+        },
+        Some(crs @ Value::Object(_)) => Crs::new(Some(crs.to_string()), None),
+        _ => Crs::default(),
+    }
+}
+
+// building arrays ----------------------------------------------------------
+
+// an owned buffer. Typed so that the buffer is aligned for its values.
+enum Buffer {
+    Validity(Vec<u8>),
+    F64(Vec<f64>),
+    I32(Vec<i32>),
+    I64(Vec<i64>),
+    Bytes(Vec<u8>),
+}
+
+impl Buffer {
+    fn as_ptr(&self) -> *const c_void {
+        match self {
+            Buffer::Validity(x) | Buffer::Bytes(x) => x.as_ptr() as *const c_void,
+            Buffer::F64(x) => x.as_ptr() as *const c_void,
+            Buffer::I32(x) => x.as_ptr() as *const c_void,
+            Buffer::I64(x) => x.as_ptr() as *const c_void,
+        }
+    }
+}
+
+// an array and its field before it is exported
+struct ArrowNode {
+    format: &'static str,
+    name: &'static str,
+    metadata: Option<Vec<u8>>,
+    length: usize,
+    null_count: usize,
+    // `None` is a null pointer such as an omitted validity buffer
+    buffers: Vec<Option<Buffer>>,
+    children: Vec<ArrowNode>,
+}
+
+// creates a validity bitmap. Omitted when there are no nulls.
+fn validity_buffer(x: &[bool]) -> (Option<Buffer>, usize) {
+    let null_count = x.iter().filter(|valid| !**valid).count();
+    if null_count == 0 {
+        return (None, 0);
+    }
+
+    let mut bits = vec![0u8; x.len().div_ceil(8)];
+    for (i, _) in x.iter().enumerate().filter(|(_, valid)| **valid) {
+        bits[i / 8] |= 1 << (i % 8);
+    }
+    (Some(Buffer::Validity(bits)), null_count)
+}
+
+fn coords_node(name: &'static str, coords: &[Coord]) -> ArrowNode {
+    let ordinate = |name, values: Vec<f64>| ArrowNode {
+        format: "g",
+        name,
+        metadata: None,
+        length: values.len(),
+        null_count: 0,
+        buffers: vec![None, Some(Buffer::F64(values))],
+        children: vec![],
+    };
+
+    ArrowNode {
+        format: "+s",
+        name,
+        metadata: None,
+        length: coords.len(),
+        null_count: 0,
+        buffers: vec![None],
+        children: vec![
+            ordinate("x", coords.iter().map(|c| c.x).collect()),
+            ordinate("y", coords.iter().map(|c| c.y).collect()),
+        ],
+    }
+}
+
+fn list_node(name: &'static str, offsets: Vec<i32>, child: ArrowNode) -> ArrowNode {
+    ArrowNode {
+        format: "+l",
+        name,
+        metadata: None,
+        length: offsets.len() - 1,
+        null_count: 0,
+        buffers: vec![None, Some(Buffer::I32(offsets))],
+        children: vec![child],
+    }
+}
+
+// the root of an extension array holds the validity and extension metadata
+fn extension_root(
+    mut node: ArrowNode,
+    typ: GeoArrowType,
+    validity: &[bool],
+    metadata: String,
+) -> ArrowNode {
+    let (validity, null_count) = validity_buffer(validity);
+    node.name = "geometry";
+    node.buffers[0] = validity;
+    node.null_count = null_count;
+    node.metadata = Some(encode_metadata(&[
+        ("ARROW:extension:name", typ.extension_name()),
+        ("ARROW:extension:metadata", &metadata),
+    ]));
+    node
+}
+
+// WKB is written as `binary` unless the offsets overflow an int32
+fn wkb_node(x: &[Option<Geom>], metadata: String) -> ArrowNode {
+    let mut data = Vec::new();
+    let mut offsets: Vec<i64> = vec![0];
+    let validity = x.iter().map(Option::is_some).collect::<Vec<bool>>();
+
+    for xi in x {
+        if let Some(geom) = xi {
+            data.extend(geom.to_wkb(ByteOrder::LittleEndian));
+        }
+        offsets.push(data.len() as i64);
+    }
+
+    let (format, offsets) = if data.len() <= i32::MAX as usize {
+        (
+            "z",
+            Buffer::I32(offsets.into_iter().map(|o| o as i32).collect()),
+        )
+    } else {
+        ("Z", Buffer::I64(offsets))
+    };
+
+    let node = ArrowNode {
+        format,
+        name: "geometry",
+        metadata: None,
+        length: x.len(),
+        null_count: 0,
+        buffers: vec![None, Some(offsets), Some(Buffer::Bytes(data))],
+        children: vec![],
+    };
+
+    extension_root(node, GeoArrowType::Wkb, &validity, metadata)
+}
+
+// Accumulates the coordinates and the offsets of each level of nesting of a
+// native geoarrow array. `offsets[0]` are the offsets of the geometries.
+struct NativeBuilder {
+    typ: GeoArrowType,
+    coords: Vec<Coord>,
+    offsets: Vec<Vec<i32>>,
+    validity: Vec<bool>,
+}
+
+impl NativeBuilder {
+    fn new(typ: GeoArrowType) -> Self {
+        NativeBuilder {
+            typ,
+            coords: Vec::new(),
+            offsets: vec![vec![0]; typ.list_names().len()],
+            validity: Vec::new(),
+        }
+    }
+
+    // the number of elements in the level below `level`
+    fn level_len(&self, level: usize) -> i32 {
+        match self.offsets.get(level + 1) {
+            Some(offsets) => offsets.len() as i32 - 1,
+            None => self.coords.len() as i32,
+        }
+    }
+
+    fn push_coords(&mut self, level: usize, x: &[Coord]) {
+        self.coords.extend_from_slice(x);
+        self.offsets[level].push(self.coords.len() as i32);
+    }
+
+    fn push_polygon(&mut self, level: usize, x: &Polygon) {
+        if !x.exterior().0.is_empty() {
+            self.push_coords(level + 1, &x.exterior().0);
+            for interior in x.interiors() {
+                self.push_coords(level + 1, &interior.0);
+            }
+        }
+        let n = self.level_len(level);
+        self.offsets[level].push(n);
+    }
+
+    fn push(&mut self, x: Option<&Geometry>) -> Result<(), SfConversionError> {
+        self.validity.push(x.is_some());
+
+        let x = match x {
+            Some(x) => x,
+            None => {
+                match self.typ {
+                    GeoArrowType::Point => self.coords.push(Coord {
+                        x: f64::NAN,
+                        y: f64::NAN,
+                    }),
+                    _ => {
+                        let n = self.level_len(0);
+                        self.offsets[0].push(n);
+                    }
+                }
+                return Ok(());
+            }
+        };
+
+        match (self.typ, x) {
+            (GeoArrowType::Point, Geometry::Point(x)) => self.coords.push(x.0),
+            (GeoArrowType::LineString, Geometry::LineString(x)) => self.push_coords(0, &x.0),
+            (GeoArrowType::LineString, Geometry::Line(x)) => self.push_coords(0, &[x.start, x.end]),
+            (GeoArrowType::Polygon, Geometry::Polygon(x)) => self.push_polygon(0, x),
+            (GeoArrowType::Polygon, Geometry::Rect(x)) => self.push_polygon(0, &x.to_polygon()),
+            (GeoArrowType::Polygon, Geometry::Triangle(x)) => self.push_polygon(0, &x.to_polygon()),
+            (GeoArrowType::MultiPoint, Geometry::MultiPoint(x)) => {
+                let coords = x.iter().map(|p| p.0).collect::<Vec<Coord>>();
+                self.push_coords(0, &coords);
+            }
+            (GeoArrowType::MultiLineString, Geometry::MultiLineString(x)) => {
+                for line in x {
+                    self.push_coords(1, &line.0);
+                }
+                let n = self.level_len(0);
+                self.offsets[0].push(n);
+            }
+            (GeoArrowType::MultiPolygon, Geometry::MultiPolygon(x)) => {
+                for poly in x {
+                    self.push_polygon(1, poly);
+                }
+                let n = self.level_len(0);
+                self.offsets[0].push(n);
+            }
+            (_, x) => return Err(SfConversionError::UnsupportedType(sfg_type(x).to_string())),
+        }
+
+        Ok(())
+    }
+
+    fn finish(self, metadata: String) -> ArrowNode {
+        let names = self.typ.list_names();
+        let mut node = coords_node(names.last().copied().unwrap_or("geometry"), &self.coords);
+
+        for (level, offsets) in self.offsets.into_iter().enumerate().rev() {
+            let name = if level == 0 {
+                "geometry"
+            } else {
+                names[level - 1]
+            };
+            node = list_node(name, offsets, node);
+        }
+
+        extension_root(node, self.typ, &self.validity, metadata)
+    }
+}
+
+// exporting ----------------------------------------------------------------
+
+struct SchemaPrivate {
+    format: CString,
+    name: CString,
+    metadata: Option<Vec<u8>>,
+    children: Vec<*mut ArrowSchema>,
+}
+
+struct ArrayPrivate {
+    _buffers: Vec<Option<Buffer>>,
+    buffer_ptrs: Vec<*const c_void>,
+    children: Vec<*mut ArrowArray>,
+}
+
+unsafe extern "C" fn release_schema(schema: *mut ArrowSchema) {
+    if schema.is_null() || (*schema).release.is_none() {
+        return;
+    }
+
+    let private = Box::from_raw((*schema).private_data as *mut SchemaPrivate);
+    for child in private.children {
+        // dropping the child calls its release callback
+        drop(Box::from_raw(child));
+    }
+
+    (*schema).release = None;
+}
+
+unsafe extern "C" fn release_array(array: *mut ArrowArray) {
+    if array.is_null() || (*array).release.is_none() {
+        return;
+    }
+
+    let private = Box::from_raw((*array).private_data as *mut ArrayPrivate);
+    for child in private.children {
+        drop(Box::from_raw(child));
+    }
+
+    (*array).release = None;
+}
+
+fn export_node(node: ArrowNode) -> (ArrowSchema, ArrowArray) {
+    let mut child_schemas = Vec::with_capacity(node.children.len());
+    let mut child_arrays = Vec::with_capacity(node.children.len());
+
+    for child in node.children {
+        let (schema, array) = export_node(child);
+        child_schemas.push(Box::into_raw(Box::new(schema)));
+        child_arrays.push(Box::into_raw(Box::new(array)));
+    }
+
+    let n_children = child_schemas.len() as i64;
+
+    let mut schema_private = Box::new(SchemaPrivate {
+        format: CString::new(node.format).unwrap(),
+        name: CString::new(node.name).unwrap(),
+        metadata: node.metadata,
+        children: child_schemas,
+    });
+
+    let schema = ArrowSchema {
+        format: schema_private.format.as_ptr(),
+        name: schema_private.name.as_ptr(),
+        metadata: schema_private
+            .metadata
+            .as_ref()
+            .map_or(std::ptr::null(), |m| m.as_ptr() as *const c_char),
+        flags: ARROW_FLAG_NULLABLE,
+        n_children,
+        children: schema_private.children.as_mut_ptr(),
+        dictionary: std::ptr::null_mut(),
+        release: Some(release_schema),
+        private_data: Box::into_raw(schema_private) as *mut c_void,
+    };
+
+    let buffer_ptrs = node
+        .buffers
+        .iter()
+        .map(|buf| buf.as_ref().map_or(std::ptr::null(), Buffer::as_ptr))
+        .collect::<Vec<*const c_void>>();
+
+    let mut array_private = Box::new(ArrayPrivate {
+        _buffers: node.buffers,
+        buffer_ptrs,
+        children: child_arrays,
+    });
+
+    let array = ArrowArray {
+        length: node.length as i64,
+        null_count: node.null_count as i64,
+        offset: 0,
+        n_buffers: array_private.buffer_ptrs.len() as i64,
+        n_children,
+        buffers: array_private.buffer_ptrs.as_mut_ptr(),
+        children: array_private.children.as_mut_ptr(),
+        dictionary: std::ptr::null_mut(),
+        release: Some(release_array),
+        private_data: Box::into_raw(array_private) as *mut c_void,
+    };
+
+    (schema, array)
+}
+
+// importing ----------------------------------------------------------------
+
+// a borrowed array and its schema
+#[derive(Clone, Copy)]
+struct ArrowView<'a> {
+    schema: &'a ArrowSchema,
+    array: &'a ArrowArray,
+}
+
+impl<'a> ArrowView<'a> {
+    unsafe fn format(&self) -> &'a str {
+        CStr::from_ptr(self.schema.format).to_str().unwrap_or("")
+    }
+
+    unsafe fn child(&self, i: usize) -> Result<ArrowView<'a>, SfConversionError> {
+        if i as i64 >= self.array.n_children || i as i64 >= self.schema.n_children {
+            return Err(SfConversionError::Parse(format!(
+                "expected array with format `{}` to have a child array",
+                self.format()
+            )));
+        }
+
+        Ok(ArrowView {
+            schema: &**self.schema.children.add(i),
+            array: &**self.array.children.add(i),
+        })
+    }
+
+    // the physical index of the ith element
+    fn index(&self, i: usize) -> usize {
+        self.array.offset as usize + i
+    }
+
+    unsafe fn buffer(&self, i: usize) -> *const u8 {
+        *self.array.buffers.add(i) as *const u8
+    }
+
+    unsafe fn is_valid(&self, i: usize) -> bool {
+        let validity = self.buffer(0);
+        if self.array.null_count == 0 || validity.is_null() {
+            return true;
+        }
+
+        let idx = self.index(i);
+        *validity.add(idx / 8) & (1 << (idx % 8)) != 0
+    }
+
+    unsafe fn f64(&self, i: usize) -> f64 {
+        std::ptr::read_unaligned((self.buffer(1) as *const f64).add(self.index(i)))
+    }
+
+    // the start and end of the ith element of a list
+    unsafe fn offsets(&self, i: usize) -> Result<(usize, usize), SfConversionError> {
+        let idx = self.index(i);
+        match self.format() {
+            "+l" => {
+                let offsets = self.buffer(1) as *const i32;
+                let start = std::ptr::read_unaligned(offsets.add(idx));
+                let end = std::ptr::read_unaligned(offsets.add(idx + 1));
+                Ok((start as usize, end as usize))
+            }
+            "+L" => {
+                let offsets = self.buffer(1) as *const i64;
+                let start = std::ptr::read_unaligned(offsets.add(idx));
+                let end = std::ptr::read_unaligned(offsets.add(idx + 1));
+                Ok((start as usize, end as usize))
+            }
+            fmt => Err(SfConversionError::Parse(format!(
+                "expected a list array, found format `{fmt}`"
+            ))),
+        }
+    }
+
+    // coordinates are separated (a struct of x and y) or interleaved
+    // (a fixed size list of 2)
+    unsafe fn coord(&self, i: usize) -> Result<Coord, SfConversionError> {
+        match self.format() {
+            "+s" => Ok(Coord {
+                x: self.child(0)?.f64(self.index(i)),
+                y: self.child(1)?.f64(self.index(i)),
+            }),
+            "+w:2" => {
+                let values = self.child(0)?;
+                let idx = self.index(i) * 2;
+                Ok(Coord {
+                    x: values.f64(idx),
+                    y: values.f64(idx + 1),
+                })
+            }
+            fmt => Err(SfConversionError::Parse(format!(
+                "expected XY coordinates, found format `{fmt}`"
+            ))),
+        }
+    }
+
+    unsafe fn binary(&self, i: usize) -> Result<&'a [u8], SfConversionError> {
+        let idx = self.index(i);
+        let (start, end) = match self.format() {
+            "z" => {
+                let offsets = self.buffer(1) as *const i32;
+                (
+                    std::ptr::read_unaligned(offsets.add(idx)) as usize,
+                    std::ptr::read_unaligned(offsets.add(idx + 1)) as usize,
+                )
+            }
+            "Z" => {
+                let offsets = self.buffer(1) as *const i64;
+                (
+                    std::ptr::read_unaligned(offsets.add(idx)) as usize,
+                    std::ptr::read_unaligned(offsets.add(idx + 1)) as usize,
+                )
+            }
+            fmt => {
+                return Err(SfConversionError::Parse(format!(
+                    "expected a binary array, found format `{fmt}`"
+                )))
+            }
+        };
+
+        Ok(std::slice::from_raw_parts(
+            self.buffer(2).add(start),
+            end - start,
+        ))
+    }
+}
+
+unsafe fn read_coords(
+    x: ArrowView,
+    start: usize,
+    end: usize,
+) -> Result<Vec<Coord>, SfConversionError> {
+    (start..end).map(|i| x.coord(i)).collect()
+}
+
+unsafe fn read_linestring(x: ArrowView, i: usize) -> Result<LineString, SfConversionError> {
+    let (start, end) = x.offsets(i)?;
+    Ok(LineString::new(read_coords(x.child(0)?, start, end)?))
+}
+
+unsafe fn read_polygon(x: ArrowView, i: usize) -> Result<Polygon, SfConversionError> {
+    let (start, end) = x.offsets(i)?;
+    let rings_view = x.child(0)?;
+
+    let rings = (start..end)
+        .map(|j| {
+            let ring = read_linestring(rings_view, j)?;
+            validate_ring(&ring.0)?;
+            Ok(ring)
+        })
+        .collect::<Result<Vec<LineString>, SfConversionError>>()?;
+
+    Ok(polygon_from_rings(rings))
+}
+
+// reads the parts of a multi geometry
+unsafe fn read_multi<T>(
+    x: ArrowView,
+    i: usize,
+    read_part: unsafe fn(ArrowView, usize) -> Result<T, SfConversionError>,
+) -> Result<Vec<T>, SfConversionError> {
+    let (start, end) = x.offsets(i)?;
+    let parts = x.child(0)?;
+    (start..end).map(|j| read_part(parts, j)).collect()
+}
@@ -0,0 +1,479 @@
+//! Convert between `Geom` and GeoJSON
+//!
+//! Geometries are converted to and from GeoJSON geometry objects
+//! ([RFC 7946](https://datatracker.ietf.org/doc/html/rfc7946)). sf data.frames
+//! are converted to and from a `FeatureCollection` where the non-geometry
+//! columns are the `properties` of each `Feature`.
+//!
+//! Empty geometries have empty `coordinates` or `geometries` arrays. `POINT EMPTY`
+//! is `{"type": "Point", "coordinates": []}`. Missing geometries are `null`.
+//!
+//! `Line`, `Rect`, and `Triangle` are written as a `LineString` and `Polygon`
+//! respectively. Only two dimensional positions can be read. Positions with
+//! altitude return [`SfConversionError::WrongDimensions`].
+//!
+//! GeoJSON coordinates are always longitude and latitude (WGS 84). An sf
+//! data.frame read from a `FeatureCollection` has the CRS `EPSG:4326`.
+use crate::{
+    constructors::{polygon_from_rings, validate_ring},
+    crs::Crs,
+    error::SfConversionError,
+    fromsf::{factor_level, sf_to_data_frame, sfc_to_geoms, SfDataFrame},
+    tosf::to_sf,
+    Geom,
+};
+use extendr_api::prelude::*;
+use extendr_api::Rtype;
+use geo_types::{
+    Coord, Geometry, GeometryCollection, LineString, MultiLineString, MultiPoint, MultiPolygon,
+    Point, Polygon,
+};
+use serde_json::{json, Map, Value};
+use std::result::Result;
+
+impl Geom {
+    /// Convert the geometry to a GeoJSON geometry object
+    pub fn to_geojson(&self) -> Value {
+        geometry_to_geojson(&self.geom)
+    }
+}
+
+/// Convert a `Geometry` to a GeoJSON geometry object
+pub fn geometry_to_geojson(x: &Geometry) -> Value {
+    match x {
+        Geometry::Point(x) => json!({ "type": "Point", "coordinates": point_position(x) }),
+        Geometry::MultiPoint(x) => json!({
+            "type": "MultiPoint",
+            "coordinates": x.iter().map(point_position).collect::<Vec<Value>>()
+        }),
+        Geometry::Line(x) => json!({
+            "type": "LineString",
+            "coordinates": [position(&x.start), position(&x.end)]
+        }),
+        Geometry::LineString(x) => json!({ "type": "LineString", "coordinates": positions(x) }),
+        Geometry::MultiLineString(x) => json!({
+            "type": "MultiLineString",
+            "coordinates": x.iter().map(positions).collect::<Vec<Value>>()
+        }),
+        Geometry::Polygon(x) => json!({ "type": "Polygon", "coordinates": polygon_rings(x) }),
+        Geometry::Rect(x) => json!({
+            "type": "Polygon",
+            "coordinates": polygon_rings(&x.to_polygon())
+        }),
+        Geometry::Triangle(x) => json!({
+            "type": "Polygon",
+            "coordinates": polygon_rings(&x.to_polygon())
+        }),
+        Geometry::MultiPolygon(x) => json!({
+            "type": "MultiPolygon",
+            "coordinates": x.iter().map(polygon_rings).collect::<Vec<Value>>()
+        }),
+        Geometry::GeometryCollection(x) => json!({
+            "type": "GeometryCollection",
+            "geometries": x.iter().map(geometry_to_geojson).collect::<Vec<Value>>()
+        }),
+    }
+}
+
+/// Convert a GeoJSON geometry object to a `Geom`
+pub fn geojson_to_geom(x: &Value) -> Result<Geom, SfConversionError> {
+    Ok(Geom::from(geojson_to_geometry(x)?))
+}
+
+/// Parse a GeoJSON geometry string into a `Geom`
+pub fn geojson_string_to_geom(x: &str) -> Result<Geom, SfConversionError> {
+    geojson_to_geom(&parse_json(x)?)
+}
+
+/// Convert geometries to a character vector of GeoJSON geometry strings.
+/// `None` values are `NA`.
+pub fn geoms_to_geojson(x: &[Option<Geom>]) -> Strings {
+    Strings::from_values(x.iter().map(|xi| match xi {
+        Some(geom) => Rstr::from(geom.to_geojson().to_string()),
+        None => Rstr::na(),
+    }))
+}
+
+/// Parse a character vector of GeoJSON geometry strings. `NA` values and
+/// `null` geometries are `None`.
+pub fn geojson_to_geoms(x: Strings) -> Result<Vec<Option<Geom>>, SfConversionError> {
+    x.iter()
+        .map(|xi| {
+            if xi.is_na() {
+                return Ok(None);
+            }
+
+            match parse_json(xi.as_str())? {
+                Value::Null => Ok(None),
+                geom => geojson_to_geom(&geom).map(Some),
+            }
+        })
+        .collect()
+}
+
+/// Convert an sfc object to a character vector of GeoJSON geometry strings.
+/// `NULL` geometries are `NA`.
+pub fn sfc_to_geojson(x: List) -> Result<Strings, SfConversionError> {
+    Ok(geoms_to_geojson(&sfc_to_geoms(x)?))
+}
+
+/// Convert an sf data.frame to a GeoJSON `FeatureCollection`. Each row is a
+/// `Feature` whose `properties` are the non-geometry columns.
+///
+/// Numeric, integer, logical, character, and factor columns are supported.
+/// Missing values are `null`.
+pub fn sf_to_feature_collection(x: List) -> Result<Value, SfConversionError> {
+    let SfDataFrame { geoms, columns, .. } = sf_to_data_frame(x)?;
+
+    let columns = columns
+        .into_iter()
+        .map(|(name, col)| Ok((name, PropertyColumn::try_from(col)?)))
+        .collect::<Result<Vec<(String, PropertyColumn)>, SfConversionError>>()?;
+
+    let features = geoms
+        .iter()
+        .enumerate()
+        .map(|(i, geom)| {
+            let properties = columns
+                .iter()
+                .map(|(name, col)| (name.clone(), col.value(i)))
+                .collect::<Map<String, Value>>();
+
+            json!({
+                "type": "Feature",
+                "properties": properties,
+                "geometry": geom.as_ref().map_or(Value::Null, Geom::to_geojson)
+            })
+        })
+        .collect::<Vec<Value>>();
+
+    Ok(json!({ "type": "FeatureCollection", "features": features }))
+}
+
+/// Convert an sf data.frame to a GeoJSON `FeatureCollection` string.
+/// See [`sf_to_feature_collection()`].
+pub fn sf_to_geojson(x: List) -> Result<String, SfConversionError> {
+    Ok(sf_to_feature_collection(x)?.to_string())
+}
+
+/// Convert a GeoJSON `FeatureCollection` to an sf data.frame.
+///
+/// Each property becomes a column. Columns are logical, numeric, or character
+/// depending on the property values. Properties with mixed types, arrays, or
+/// objects are written as character columns of JSON. A property missing from
+/// a feature is `NA`.
+///
+/// The geometry column is named `geometry`. If a property already has that
+/// name the geometry column is `geometry.1` (or `geometry.2` and so on).
+pub fn feature_collection_to_sf(x: &Value) -> Result<Robj, SfConversionError> {
+    if x.get("type").and_then(Value::as_str) != Some("FeatureCollection") {
+        return Err(SfConversionError::Parse(
+            "expected a GeoJSON FeatureCollection".into(),
+        ));
+    }
+
+    let features = x
+        .get("features")
+        .and_then(Value::as_array)
+        .ok_or_else(|| SfConversionError::Parse("FeatureCollection has no features".into()))?;
+
+    let n = features.len();
+    let mut geoms = Vec::with_capacity(n);
+    let mut names: Vec<String> = Vec::new();
+    let mut values: Vec<Vec<Value>> = Vec::new();
+
+    for (i, feature) in features.iter().enumerate() {
+        let geom = match feature.get("geometry") {
+            None | Some(Value::Null) => None,
+            Some(geom) => Some(geojson_to_geom(geom)?),
+        };
+        geoms.push(geom);
+
+        let props = match feature.get("properties") {
+            Some(Value::Object(props)) => props,
+            _ => continue,
+        };
+
+        for (key, val) in props {
+            let j = match names.iter().position(|nm| nm == key) {
+                Some(j) => j,
+                None => {
+                    names.push(key.clone());
+                    values.push(vec![Value::Null; n]);
+                    names.len() - 1
+                }
+            };
+            values[j][i] = val.clone();
+        }
+    }
+
+    let sf_column = geometry_column(&names);
+    let columns = names
+        .into_iter()
+        .zip(values)
+        .map(|(name, vals)| (name, property_column(vals)))
+        .collect::<Vec<(String, Robj)>>();
+
+    let mut res = SfDataFrame::new(geoms, columns).with_crs(Crs::from_epsg(4326));
+    res.sf_column = sf_column;
+    to_sf(res)
+}
+
+// `geometry` made unique among the property names
+fn geometry_column(names: &[String]) -> String {
+    let mut res = String::from("geometry");
+    let mut i = 0;
+    while names.contains(&res) {
+        i += 1;
+        res = format!("geometry.{i}");
+    }
+    res
+}
+
+/// Parse a GeoJSON `FeatureCollection` string into an sf data.frame.
+/// See [`feature_collection_to_sf()`].
+pub fn geojson_to_sf(x: &str) -> Result<Robj, SfConversionError> {
+    feature_collection_to_sf(&parse_json(x)?)
+}
+
+fn parse_json(x: &str) -> Result<Value, SfConversionError> {
+    serde_json::from_str(x).map_err(|e| SfConversionError::Parse(e.to_string()))
+}
+
+// GeoJSON writing ----------------------------------------------------------
+
+fn position(x: &Coord) -> Value {
+    json!([x.x, x.y])
+}
+
+// an empty point has an empty position
+fn point_position(x: &Point) -> Value {
+    if x.x().is_nan() && x.y().is_nan() {
+        json!([])
+    } else {
+        position(&x.0)
+    }
+}
+
+fn positions(x: &LineString) -> Value {
+    Value::Array(x.coords().map(position).collect())
+}
+
+fn polygon_rings(x: &Polygon) -> Value {
+    if x.exterior().0.is_empty() {
+        return json!([]);
+    }
+
+    let mut rings = vec![positions(x.exterior())];
+    rings.extend(x.interiors().iter().map(positions));
+    Value::Array(rings)
+}
+
+// the values of an attribute column used as feature properties
+enum PropertyColumn {
+    Doubles(Doubles),
+    Integers(Integers),
+    Factor(Integers, Strings),
+    Logicals(Logicals),
+    Strings(Strings),
+}
+
+impl TryFrom<Robj> for PropertyColumn {
+    type Error = SfConversionError;
+
+    fn try_from(x: Robj) -> Result<Self, Self::Error> {
+        let err = SfConversionError::bad_storage("atomic vector", &x);
+
+        let col = match x.rtype() {
+            Rtype::Doubles => PropertyColumn::Doubles(x.try_into().map_err(|_| err)?),
+            Rtype::Integers if x.inherits("factor") => {
+                let levels = x
+                    .get_attrib("levels")
+                    .and_then(|lvls| Strings::try_from(lvls).ok());
+
+                match (levels, Integers::try_from(x)) {
+                    (Some(levels), Ok(codes)) => PropertyColumn::Factor(codes, levels),
+                    _ => return Err(err),
+                }
+            }
+            Rtype::Integers => PropertyColumn::Integers(x.try_into().map_err(|_| err)?),
+            Rtype::Logicals => PropertyColumn::Logicals(x.try_into().map_err(|_| err)?),
+            Rtype::Strings => PropertyColumn::Strings(x.try_into().map_err(|_| err)?),
+            _ => return Err(err),
+        };
+
+        Ok(col)
+    }
+}
+
+impl PropertyColumn {
+    // the property value of the ith row. Missing values are null.
+    fn value(&self, i: usize) -> Value {
+        match self {
+            PropertyColumn::Doubles(x) => {
+                let xi = x.elt(i);
+                if xi.is_na() || xi.inner().is_nan() {
+                    Value::Null
+                } else {
+                    json!(xi.inner())
+                }
+            }
+            PropertyColumn::Integers(x) => {
+                let xi = x.elt(i);
+                if xi.is_na() {
+                    Value::Null
+                } else {
+                    json!(xi.inner())
+                }
+            }
+            PropertyColumn::Factor(x, levels) => match factor_level(levels, x.elt(i)) {
+                Some(lvl) => json!(lvl),
+                None => Value::Null,
+            },
+            PropertyColumn::Logicals(x) => {
+                let xi = x.elt(i);
+                if xi.is_na() {
+                    Value::Null
+                } else {
+                    json!(xi.is_true())
+                }
+            }
+            PropertyColumn::Strings(x) => {
+                let xi = x.elt(i);
+                if xi.is_na() {
+                    Value::Null
+                } else {
+                    json!(xi.as_str())
+                }
+            }
+        }
+    }
+}
+
+// GeoJSON reading ----------------------------------------------------------
+
+// Creates an R vector from the values of a property. The type is the
+// narrowest of logical, double, and character that holds every value.
+fn property_column(x: Vec<Value>) -> Robj {
+    let non_null = || x.iter().filter(|v| !v.is_null());
+
+    if non_null().all(Value::is_boolean) {
+        Logicals::from_values(x.iter().map(|v| match v {
+            Value::Bool(b) => Rbool::from(*b),
+            _ => Rbool::na(),
+        }))
+        .into()
+    } else if non_null().all(Value::is_number) {
+        Doubles::from_values(x.iter().map(|v| match v.as_f64() {
+            Some(n) => Rfloat::from(n),
+            None => Rfloat::na(),
+        }))
+        .into()
+    } else {
+        Strings::from_values(x.iter().map(|v| match v {
+            Value::Null => Rstr::na(),
+            Value::String(s) => Rstr::from(s.as_str()),
+            v => Rstr::from(v.to_string()),
+        }))
+        .into()
+    }
+}
+
+fn json_type(x: &Value) -> Result<&str, SfConversionError> {
+    x.get("type")
+        .and_then(Value::as_str)
+        .ok_or_else(|| SfConversionError::Parse("GeoJSON object has no `type`".into()))
+}
+
+fn json_array<'a>(x: &'a Value, field: &str) -> Result<&'a Vec<Value>, SfConversionError> {
+    x.get(field)
+        .and_then(Value::as_array)
+        .ok_or_else(|| SfConversionError::Parse(format!("expected `{field}` to be an array")))
+}
+
+fn as_array(x: &Value) -> Result<&Vec<Value>, SfConversionError> {
+    x.as_array()
+        .ok_or_else(|| SfConversionError::Parse(format!("expected an array, found `{x}`")))
+}
+
+fn read_position(x: &Value) -> Result<Coord, SfConversionError> {
+    let pos = as_array(x)?;
+    if pos.len() != 2 {
+        return Err(SfConversionError::WrongDimensions {
+            expected: 2,
+            found: pos.len(),
+        });
+    }
+
+    let ord = |v: &Value| {
+        v.as_f64()
+            .ok_or_else(|| SfConversionError::Parse(format!("expected a number, found `{v}`")))
+    };
+
+    Ok(Coord {
+        x: ord(&pos[0])?,
+        y: ord(&pos[1])?,
+    })
+}
+
+fn read_point(x: &Value) -> Result<Point, SfConversionError> {
+    if as_array(x)?.is_empty() {
+        return Ok(Point::new(f64::NAN, f64::NAN));
+    }
+    Ok(Point::from(read_position(x)?))
+}
+
+fn read_linestring(x: &Value) -> Result<LineString, SfConversionError> {
+    let coords = as_array(x)?
+        .iter()
+        .map(read_position)
+        .collect::<Result<Vec<Coord>, SfConversionError>>()?;
+    Ok(LineString::new(coords))
+}
+
+fn read_polygon(x: &Value) -> Result<Polygon, SfConversionError> {
+    let rings = as_array(x)?
+        .iter()
+        .map(|ring| {
+            let ring = read_linestring(ring)?;
+            validate_ring(&ring.0)?;
+            Ok(ring)
+        })
+        .collect::<Result<Vec<LineString>, SfConversionError>>()?;
+
+    Ok(polygon_from_rings(rings))
+}
+
+fn read_all<T>(
+    x: &[Value],
+    read: fn(&Value) -> Result<T, SfConversionError>,
+) -> Result<Vec<T>, SfConversionError> {
+    x.iter().map(read).collect()
+}
+
+fn geojson_to_geometry(x: &Value) -> Result<Geometry, SfConversionError> {
+    let typ = json_type(x)?;
+
+    if typ == "GeometryCollection" {
+        let geoms = read_all(json_array(x, "geometries")?, geojson_to_geometry)?;
+        return Ok(GeometryCollection::new_from(geoms).into());
+    }
+
+    let coords = x
+        .get("coordinates")
+        .ok_or_else(|| SfConversionError::Parse("GeoJSON geometry has no `coordinates`".into()))?;
+
+    let geom: Geometry = match typ {
+        "Point" => read_point(coords)?.into(),
+        "MultiPoint" => MultiPoint::new(read_all(as_array(coords)?, read_point)?).into(),
+        "LineString" => read_linestring(coords)?.into(),
+        "MultiLineString" => {
+            MultiLineString::new(read_all(as_array(coords)?, read_linestring)?).into()
+        }
+        "Polygon" => read_polygon(coords)?.into(),
+        "MultiPolygon" => MultiPolygon::new(read_all(as_array(coords)?, read_polygon)?).into(),
+        _ => return Err(SfConversionError::UnsupportedType(typ.to_string())),
+    };
+
+    Ok(geom)
+}
@@ -0,0 +1,375 @@
+//! A spatial index over geometry vectors
+//!
+//! [`SpatialIndex`] bulk loads the envelopes of an sfc object or rsgeo vector
+//! into an [rstar](https://docs.rs/rstar/) R-tree. Each entry remembers the
+//! position of its geometry in the original vector so that queries return
+//! indices into that vector. Indices are 0-based in Rust and 1-based when
+//! returned to R making them suitable for spatial joins.
+//!
+//! Missing geometries, and geometries without an envelope such as empty
+//! geometries (see [`Geom::to_aabb()`]), are not indexed and are never returned
+//! by a query. The remaining geometries keep their original indices so sfc
+//! objects containing empties can be indexed as is.
+//!
+//! Nearest neighbour and distance queries use the exact Euclidean distance to
+//! the indexed geometries ignoring any empty members.
+//! [`SpatialIndex::nearest_k()`] finds the nearest geometries to a geometry
+//! rather than a point by searching the tree in order of the distance between
+//! envelopes.
+//!
+//! From R the index is created with `SpatialIndex$new(x)`.
+use crate::{
+    constructors::bbox_to_rect, error::SfConversionError, fromsf::sfc_to_geoms, geometry_distance,
+    geoms_from_list, Geom,
+};
+use extendr_api::prelude::*;
+use geo_types::{Point, Rect};
+use rstar::{ParentNode, PointDistance, RTree, RTreeNode, RTreeObject, AABB};
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::result::Result;
+
+// a geometry in the tree with its position in the original vector
+#[derive(Debug, Clone)]
+struct IndexedGeom {
+    geom: Geom,
+    envelope: AABB<[f64; 2]>,
+    idx: usize,
+}
+
+impl RTreeObject for IndexedGeom {
+    type Envelope = AABB<[f64; 2]>;
+
+    fn envelope(&self) -> Self::Envelope {
+        self.envelope
+    }
+}
+
+impl PointDistance for IndexedGeom {
+    fn distance_2(&self, point: &[f64; 2]) -> f64 {
+        self.geom.distance_2(point)
+    }
+}
+
+/// An R-tree of geometries which returns the indices of the original vector
+#[derive(Debug, Clone)]
+pub struct SpatialIndex {
+    tree: RTree<IndexedGeom>,
+}
+
+impl SpatialIndex {
+    /// Bulk load an index from a vector of geometries. Missing geometries and
+    /// those without an envelope are skipped but the positions of the remaining
+    /// geometries are retained.
+    pub fn from_geoms(x: Vec<Option<Geom>>) -> Self {
+        let entries = x
+            .into_iter()
+            .enumerate()
+            .filter_map(|(idx, geom)| {
+                let geom = geom?;
+                let envelope = geom.to_aabb()?;
+                Some(IndexedGeom {
+                    geom,
+                    envelope,
+                    idx,
+                })
+            })
+            .collect();
+
+        SpatialIndex {
+            tree: RTree::bulk_load(entries),
+        }
+    }
+
+    /// The number of indexed geometries
+    pub fn n_indexed(&self) -> usize {
+        self.tree.size()
+    }
+
+    /// Indices of the geometries whose envelope intersects `rect`
+    pub fn intersecting(&self, rect: &Rect) -> Vec<usize> {
+        self.intersecting_aabb(&rect_to_aabb(rect))
+    }
+
+    fn intersecting_aabb(&self, envelope: &AABB<[f64; 2]>) -> Vec<usize> {
+        let mut res = self
+            .tree
+            .locate_in_envelope_intersecting(envelope)
+            .map(|x| x.idx)
+            .collect::<Vec<usize>>();
+        res.sort_unstable();
+        res
+    }
+
+    /// Indices of the geometries whose envelope is fully contained by `rect`
+    pub fn contained_by(&self, rect: &Rect) -> Vec<usize> {
+        let mut res = self
+            .tree
+            .locate_in_envelope(&rect_to_aabb(rect))
+            .map(|x| x.idx)
+            .collect::<Vec<usize>>();
+        res.sort_unstable();
+        res
+    }
+
+    /// Index of the geometry nearest to `point`. `None` if the index is empty.
+    pub fn nearest(&self, point: Point) -> Option<usize> {
+        self.tree
+            .nearest_neighbor(&[point.x(), point.y()])
+            .map(|x| x.idx)
+    }
+
+    /// Indices of the geometries within `distance` of `point`. A negative or
+    /// `NaN` distance matches no geometries.
+    pub fn within_distance(&self, point: Point, distance: f64) -> Vec<usize> {
+        if distance.is_nan() || distance < 0.0 {
+            return Vec::new();
+        }
+
+        let mut res = self
+            .tree
+            .locate_within_distance([point.x(), point.y()], distance.powi(2))
+            .map(|x| x.idx)
+            .collect::<Vec<usize>>();
+        res.sort_unstable();
+        res
+    }
+
+    /// The indices of and distances to the `k` geometries nearest to `x`,
+    /// nearest first. A geometry without an envelope has no neighbours.
+    pub fn nearest_k(&self, x: &Geom, k: usize) -> Vec<(usize, f64)> {
+        let Some(envelope) = x.to_aabb() else {
+            return Vec::new();
+        };
+
+        let mut res = Vec::with_capacity(k);
+        let mut queue = BinaryHeap::new();
+        queue.push(Candidate {
+            distance: 0.0,
+            node: CandidateNode::Parent(self.tree.root()),
+        });
+
+        // envelope distances are a lower bound of the geometry distance so a
+        // geometry popped from the queue is nearer than anything remaining
+        while res.len() < k {
+            let Some(candidate) = queue.pop() else { break };
+            match candidate.node {
+                CandidateNode::Leaf(leaf) => res.push((leaf.idx, candidate.distance)),
+                CandidateNode::Parent(parent) => {
+                    for child in parent.children() {
+                        let candidate = match child {
+                            RTreeNode::Leaf(leaf) => Candidate {
+                                distance: geometry_distance(&x.geom, &leaf.geom.geom),
+                                node: CandidateNode::Leaf(leaf),
+                            },
+                            RTreeNode::Parent(parent) => Candidate {
+                                distance: aabb_distance(&envelope, &parent.envelope()),
+                                node: CandidateNode::Parent(parent),
+                            },
+                        };
+                        queue.push(candidate);
+                    }
+                }
+            }
+        }
+
+        res
+    }
+
+    /// The `k` nearest geometries to each geometry of `x`
+    pub fn knn(&self, x: &[Option<Geom>], k: usize) -> Neighbours {
+        let mut res = Neighbours::default();
+
+        for (query, xi) in x.iter().enumerate() {
+            let Some(geom) = xi else { continue };
+            for (index, distance) in self.nearest_k(geom, k) {
+                res.query.push(query);
+                res.index.push(index);
+                res.distance.push(distance);
+            }
+        }
+
+        res
+    }
+
+    /// For each geometry of `x`, the indices of the geometries whose envelope
+    /// intersects its envelope. Missing geometries and those without an
+    /// envelope have no candidates.
+    pub fn candidates(&self, x: &[Option<Geom>]) -> Vec<Vec<usize>> {
+        x.iter()
+            .map(|xi| {
+                xi.as_ref()
+                    .and_then(|g| g.to_aabb())
+                    .map(|envelope| self.intersecting_aabb(&envelope))
+                    .unwrap_or_default()
+            })
+            .collect()
+    }
+}
+
+#[extendr]
+impl SpatialIndex {
+    /// Create an index from an sfc object or rsgeo vector
+    fn new(x: List) -> extendr_api::Result<Self> {
+        Ok(SpatialIndex::from_geoms(index_geoms(x)?))
+    }
+
+    /// The number of indexed geometries
+    fn size(&self) -> i32 {
+        self.n_indexed() as i32
+    }
+
+    /// Indices of geometries whose envelope intersects a bounding box
+    fn intersects_bbox(&self, bbox: Doubles) -> extendr_api::Result<Integers> {
+        Ok(r_indices(self.intersecting(&bbox_to_rect(bbox)?)))
+    }
+
+    /// Indices of geometries whose envelope is within a bounding box
+    fn within_bbox(&self, bbox: Doubles) -> extendr_api::Result<Integers> {
+        Ok(r_indices(self.contained_by(&bbox_to_rect(bbox)?)))
+    }
+
+    /// Index of the geometry nearest to a location. `NA` if the index is empty.
+    fn nearest_xy(&self, x: f64, y: f64) -> Rint {
+        match self.nearest(Point::new(x, y)) {
+            Some(idx) => Rint::from(idx as i32 + 1),
+            None => Rint::na(),
+        }
+    }
+
+    /// Indices of geometries within a distance of a location. The distance
+    /// must not be negative or `NA`.
+    fn within_distance_xy(&self, x: f64, y: f64, distance: f64) -> extendr_api::Result<Integers> {
+        if distance.is_nan() || distance < 0.0 {
+            return Err(Error::Other(format!(
+                "`distance` must be a non-negative number, found {distance}"
+            )));
+        }
+
+        Ok(r_indices(self.within_distance(Point::new(x, y), distance)))
+    }
+
+    /// A data.frame of the `k` nearest geometries to each geometry of an
+    /// sfc or rsgeo vector with the columns `query`, `index`, and `distance`.
+    /// `k` must not be negative or `NA`.
+    fn nearest_k_geoms(&self, x: List, k: i32) -> extendr_api::Result<Robj> {
+        // NA_integer_ is i32::MIN
+        let k = usize::try_from(k)
+            .map_err(|_| Error::Other(String::from("`k` must be a non-negative integer")))?;
+        Ok(self.knn(&index_geoms(x)?, k).to_data_frame())
+    }
+
+    /// A list of candidate indices for each geometry of an sfc or rsgeo vector
+    fn query_candidates(&self, x: List) -> extendr_api::Result<List> {
+        let res = self
+            .candidates(&index_geoms(x)?)
+            .into_iter()
+            .map(r_indices)
+            .collect::<List>();
+        Ok(res)
+    }
+}
+
+/// The result of a k-nearest neighbour query in long format. Each element is a
+/// pair of a query geometry and one of its neighbours in the index. Indices are
+/// 0-based.
+#[derive(Debug, Clone, Default)]
+pub struct Neighbours {
+    /// index of the query geometry
+    pub query: Vec<usize>,
+    /// index of the neighbouring geometry in the index
+    pub index: Vec<usize>,
+    /// Euclidean distance between the query geometry and its neighbour
+    pub distance: Vec<f64>,
+}
+
+impl Neighbours {
+    /// Create a data.frame with 1-based `query` and `index` columns and a
+    /// `distance` column
+    pub fn to_data_frame(&self) -> Robj {
+        let n = self.distance.len();
+        let values = [
+            r_indices(self.query.clone()).into_robj(),
+            r_indices(self.index.clone()).into_robj(),
+            Robj::from(self.distance.clone()),
+        ];
+
+        List::from_names_and_values(["query", "index", "distance"], values)
+            .unwrap()
+            .into_robj()
+            .set_attrib(
+                "row.names",
+                Integers::from_values([Rint::na(), Rint::from(-(n as i32))]),
+            )
+            .unwrap()
+            .set_class(["data.frame"])
+            .unwrap()
+            .clone()
+    }
+}
+
+// a node of the tree ordered by its distance to the query geometry
+struct Candidate<'a> {
+    distance: f64,
+    node: CandidateNode<'a>,
+}
+
+enum CandidateNode<'a> {
+    Parent(&'a ParentNode<IndexedGeom>),
+    Leaf(&'a IndexedGeom),
+}
+
+// BinaryHeap is a max heap so the ordering is reversed
+impl Ord for Candidate<'_> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.distance.total_cmp(&self.distance)
+    }
+}
+
+impl PartialOrd for Candidate<'_> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Candidate<'_> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Candidate<'_> {}
+
+extendr_module! {
+    mod index;
+    impl SpatialIndex;
+}
+
+// reads an sfc object or rsgeo vector
+fn index_geoms(x: List) -> Result<Vec<Option<Geom>>, SfConversionError> {
+    if x.inherits("sfc") {
+        sfc_to_geoms(x)
+    } else if x.inherits("rsgeo") {
+        geoms_from_list(x)
+    } else {
+        Err(SfConversionError::MissingClass("sfc"))
+    }
+}
+
+fn rect_to_aabb(x: &Rect) -> AABB<[f64; 2]> {
+    AABB::from_corners(x.min().into(), x.max().into())
+}
+
+// the minimum distance between two envelopes. `0` when they intersect.
+fn aabb_distance(a: &AABB<[f64; 2]>, b: &AABB<[f64; 2]>) -> f64 {
+    let (a_min, a_max) = (a.lower(), a.upper());
+    let (b_min, b_max) = (b.lower(), b.upper());
+    let dx = (a_min[0] - b_max[0]).max(b_min[0] - a_max[0]).max(0.0);
+    let dy = (a_min[1] - b_max[1]).max(b_min[1] - a_max[1]).max(0.0);
+    dx.hypot(dy)
+}
+
+// 0-based indices to 1-based R indices
+fn r_indices(x: Vec<usize>) -> Integers {
+    Integers::from_values(x.into_iter().map(|i| i as i32 + 1))
+}
@@ -1,47 +1,53 @@
 use extendr_api::prelude::*;
 
 pub mod constructors;
+pub mod coords;
+pub mod crs;
+pub mod envelope;
+pub mod error;
 pub mod esri;
+pub mod ewkb;
 pub mod fromsf;
+pub mod geoarrow;
+pub mod geojson;
+pub mod index;
 pub mod sfg;
+pub mod sgbp;
 pub mod tosf;
 pub mod vctrs;
+pub mod wk;
+pub mod wkb;
+pub mod wkt;
+pub mod zm;
 
 use geo_types::{
-    Geometry, Line, LineString, MultiLineString, MultiPoint, MultiPolygon, Point, Polygon, Rect,
+    Geometry, GeometryCollection, Line, LineString, MultiLineString, MultiPoint, MultiPolygon,
+    Point, Polygon, Rect, Triangle,
 };
 
-use geo::BoundingRect;
+use error::SfConversionError;
+use geo::{Distance, Euclidean};
 use rstar::primitives::CachedEnvelope;
+use std::borrow::Cow;
+use std::result::Result;
 
 extendr_module! {
     mod sfconversions;
     impl Geom;
+    use index;
 }
 
-/// Implement RTreeObject for Geom
-impl rstar::RTreeObject for Geom {
-    type Envelope = rstar::AABB<[f64; 2]>;
-    fn envelope(&self) -> Self::Envelope {
-        let bbox = self.geom.bounding_rect().unwrap();
-        let ll = bbox.min(); //lower left x coord
-        let ur = bbox.max(); // upper right y
-        rstar::AABB::from_corners(ll.into(), ur.into())
+/// Implement PointDistance for Geom using the Euclidean distance from the point
+/// to the geometry. Points within a polygon have a distance of `0`. Empty
+/// geometries are infinitely far from every point and empty members of
+/// multi-part geometries and collections are ignored.
+impl rstar::PointDistance for Geom {
+    fn distance_2(&self, point: &[f64; 2]) -> f64 {
+        let pnt: Geometry = Point::new(point[0], point[1]).into();
+        geometry_distance(&pnt, &self.geom).powi(2)
     }
 }
 
-// impl rstar::PointDistance for Geom {
-//     fn distance_2(
-//             &self,
-//             point: &<Self::Envelope as rstar::Envelope>::Point,
-//         ) -> <<Self::Envelope as rstar::Envelope>::Point as rstar::Point>::Scalar {
-//             let pnt = geo_types::coord!{x: point[0], y: point[1]};
-//             let pnt = geo_types::point!(pnt);
-//             let d = &self.geom.euclidean_distance(&pnt);
-//             d.powi(2)
-//     }
-// }
-
 /// The `Geom` struct is the backbone of sfconversions. It provides
 /// an itermediary between extendr and geo / geo_types as required
 /// by the orphan rule.
@@ -74,8 +80,89 @@ where
 #[extendr]
 impl Geom {
     pub fn print(&self) -> String {
-        let fstr = format!("{:?}", self.geom);
-        fstr.splitn(2, '(').nth(1).unwrap_or("").to_string()
+        self.to_wkt()
+    }
+}
+
+impl Geom {
+    /// Returns `true` if the geometry is empty. See [`geometry_is_empty()`].
+    pub fn is_empty(&self) -> bool {
+        geometry_is_empty(&self.geom)
+    }
+}
+
+/// Determine if a `Geometry` is empty.
+///
+/// geo-types has no empty point. Following the WKB convention `POINT EMPTY`
+/// is represented as a `Point` with `NaN` coordinates. Other geometries are
+/// empty when they have no coordinates or components. `Line`, `Rect`, and
+/// `Triangle` can never be empty.
+pub fn geometry_is_empty(x: &Geometry) -> bool {
+    match x {
+        Geometry::Point(p) => p.x().is_nan() && p.y().is_nan(),
+        Geometry::MultiPoint(x) => x.0.is_empty(),
+        Geometry::LineString(x) => x.0.is_empty(),
+        Geometry::MultiLineString(x) => x.0.is_empty(),
+        Geometry::Polygon(x) => x.exterior().0.is_empty(),
+        Geometry::MultiPolygon(x) => x.0.is_empty(),
+        Geometry::GeometryCollection(x) => x.0.is_empty(),
+        Geometry::Line(_) | Geometry::Rect(_) | Geometry::Triangle(_) => false,
+    }
+}
+
+// The Euclidean distance between geometries ignoring empty members. geo
+// measures the distance to an empty polygon as `0` so they are removed first.
+// Empty geometries are infinitely far from every geometry.
+pub(crate) fn geometry_distance(x: &Geometry, y: &Geometry) -> f64 {
+    match (without_empty(x), without_empty(y)) {
+        (Some(x), Some(y)) => Euclidean.distance(&*x, &*y),
+        _ => f64::INFINITY,
+    }
+}
+
+// a geometry with its empty members removed recursively. Borrowed when there
+// are no empty members and `None` when the geometry is empty.
+fn without_empty(geom: &Geometry) -> Option<Cow<'_, Geometry>> {
+    if geometry_is_empty(geom) {
+        return None;
+    }
+
+    let res: Geometry = match geom {
+        Geometry::MultiPoint(x) => {
+            let is_empty = |p: &Point| p.x().is_nan() && p.y().is_nan();
+            if !x.iter().any(is_empty) {
+                return Some(Cow::Borrowed(geom));
+            }
+            MultiPoint::new(x.iter().filter(|p| !is_empty(*p)).copied().collect()).into()
+        }
+        Geometry::MultiLineString(x) => {
+            if !x.iter().any(|l| l.0.is_empty()) {
+                return Some(Cow::Borrowed(geom));
+            }
+            MultiLineString::new(x.iter().filter(|l| !l.0.is_empty()).cloned().collect()).into()
+        }
+        Geometry::MultiPolygon(x) => {
+            if !x.iter().any(|p| p.exterior().0.is_empty()) {
+                return Some(Cow::Borrowed(geom));
+            }
+            let polys = x.iter().filter(|p| !p.exterior().0.is_empty());
+            MultiPolygon::new(polys.cloned().collect()).into()
+        }
+        Geometry::GeometryCollection(x) => {
+            let members = x.iter().filter_map(without_empty).collect::<Vec<_>>();
+            let borrowed = members.iter().all(|m| matches!(m, Cow::Borrowed(_)));
+            if borrowed && members.len() == x.0.len() {
+                return Some(Cow::Borrowed(geom));
+            }
+            GeometryCollection::new_from(members.into_iter().map(Cow::into_owned).collect()).into()
+        }
+        _ => return Some(Cow::Borrowed(geom)),
+    };
+
+    if geometry_is_empty(&res) {
+        None
+    } else {
+        Some(Cow::Owned(res))
     }
 }
 
@@ -129,6 +216,13 @@ impl From<MultiLineString> for Geom {
     }
 }
 
+impl From<GeometryCollection> for Geom {
+    fn from(gc: GeometryCollection) -> Self {
+        let x: Geometry = gc.into();
+        Geom { geom: x }
+    }
+}
+
 impl From<Rect> for Geom {
     fn from(r: Rect) -> Self {
         let x: Geometry = r.into();
@@ -143,80 +237,153 @@ impl From<Line> for Geom {
     }
 }
 
-// impl From<Geom> for MultiPolygon {
-//     fn from(geom: Geom) -> Self {
-//         let x = geom.geom;
-//         let x: MultiPolygon = x.try_into().unwrap();
-//         x
-//     }
-// }
+impl From<Triangle> for Geom {
+    fn from(t: Triangle) -> Self {
+        let x: Geometry = t.into();
+        Geom { geom: x }
+    }
+}
 
 // TO geo-types from Geom
-impl From<Geom> for Polygon {
-    fn from(geom: Geom) -> Self {
-        let x = geom.geom;
-        let x: Polygon = x.try_into().unwrap();
-        x
-    }
+// Implements `TryFrom<Geom>` and `TryFrom<&Geom>`, which clones, for a
+// geo-types primitive. A different geometry type returns
+// `SfConversionError::MismatchedGeometry` naming the type found.
+macro_rules! impl_try_from_geom {
+    ($($typ:ident),*) => {
+        $(
+            impl TryFrom<Geom> for $typ {
+                type Error = SfConversionError;
+
+                fn try_from(geom: Geom) -> Result<Self, Self::Error> {
+                    match geom.geom {
+                        Geometry::$typ(x) => Ok(x),
+                        x => Err(SfConversionError::MismatchedGeometry {
+                            expected: stringify!($typ),
+                            found: geometry_name(&x),
+                        }),
+                    }
+                }
+            }
+
+            impl TryFrom<&Geom> for $typ {
+                type Error = SfConversionError;
+
+                fn try_from(geom: &Geom) -> Result<Self, Self::Error> {
+                    match &geom.geom {
+                        Geometry::$typ(x) => Ok(x.clone()),
+                        x => Err(SfConversionError::MismatchedGeometry {
+                            expected: stringify!($typ),
+                            found: geometry_name(x),
+                        }),
+                    }
+                }
+            }
+        )*
+    };
 }
 
-impl From<Geom> for LineString {
-    fn from(geom: Geom) -> Self {
-        let x = geom.geom;
-        let x: LineString = x.try_into().unwrap();
-        x
+impl_try_from_geom!(
+    Point,
+    MultiPoint,
+    LineString,
+    MultiLineString,
+    Polygon,
+    MultiPolygon,
+    GeometryCollection,
+    Line,
+    Rect,
+    Triangle
+);
+
+/// extendr does not permit taking ownership of the pointers it creates
+/// for structs. This impl clones the struct to create an owned struct.
+impl TryFrom<Robj> for Geom {
+    type Error = SfConversionError;
+
+    fn try_from(robj: Robj) -> Result<Self, Self::Error> {
+        Ok(geom_ref(&robj)?.clone())
     }
 }
 
-impl From<Geom> for Point {
-    fn from(geom: Geom) -> Self {
-        let x = geom.geom;
-        let x: Point = x.try_into().unwrap();
-        x
-    }
+// borrow the Geom behind an external pointer
+fn geom_ref(robj: &Robj) -> Result<&'static Geom, SfConversionError> {
+    <&Geom>::try_from(robj).map_err(|_| SfConversionError::bad_storage("Geom", robj))
 }
 
-/// extendr does not permit taking ownership of the pointers it creates
-/// for structs. This impl clones the struct to create an owned struct.
-impl From<Robj> for Geom {
-    fn from(robj: Robj) -> Self {
-        <&Geom>::try_from(&robj).unwrap().clone()
+/// The name of the geo-types `Geometry` variant e.g. `"MultiPolygon"`
+pub fn geometry_name(x: &Geometry) -> &'static str {
+    match x {
+        Geometry::Point(_) => "Point",
+        Geometry::Line(_) => "Line",
+        Geometry::LineString(_) => "LineString",
+        Geometry::Polygon(_) => "Polygon",
+        Geometry::MultiPoint(_) => "MultiPoint",
+        Geometry::MultiLineString(_) => "MultiLineString",
+        Geometry::MultiPolygon(_) => "MultiPolygon",
+        Geometry::GeometryCollection(_) => "GeometryCollection",
+        Geometry::Rect(_) => "Rect",
+        Geometry::Triangle(_) => "Triangle",
     }
 }
 
-// This is infallible. It requires that there are no missing geometries.
-// In the case that there are missing geometries, they must be handled
-// independently. This implementation clones the pointers
+// This requires that every element is either a Geom pointer or NULL.
+// This implementation clones the pointers
 // Missing geometries are recorded as a NULL (extendr_api::NULL)
-pub fn geoms_from_list(x: List) -> Vec<Option<Geom>> {
+pub fn geoms_from_list(x: List) -> Result<Vec<Option<Geom>>, SfConversionError> {
     x.into_iter()
         .map(|(_, robj)| {
             if robj.is_null() {
-                None
+                Ok(None)
             } else {
-                Some(Geom::from(robj))
+                Geom::try_from(robj).map(Some)
             }
         })
-        .collect::<Vec<Option<Geom>>>()
+        .collect::<Result<Vec<Option<Geom>>, SfConversionError>>()
 }
 
-pub fn geoms_ref_from_list(x: List) -> Vec<Option<&'static Geom>> {
+pub fn geoms_ref_from_list(x: List) -> Result<Vec<Option<&'static Geom>>, SfConversionError> {
     x.into_iter()
         .map(|(_, robj)| {
             if robj.is_null() {
-                None
+                Ok(None)
             } else {
-                Some(<&Geom>::try_from(&robj).unwrap())
+                geom_ref(&robj).map(Some)
             }
         })
-        .collect::<Vec<Option<&Geom>>>()
+        .collect::<Result<Vec<Option<&Geom>>, SfConversionError>>()
 }
 
-pub fn geometry_from_list(x: List) -> Vec<Option<Geometry>> {
+pub fn geometry_from_list(x: List) -> Result<Vec<Option<Geometry>>, SfConversionError> {
     x.into_iter()
-        .map(|(_, xi)| match <&Geom>::try_from(&xi) {
-            Ok(g) => Some(g.geom.clone()),
-            Err(_) => None,
+        .map(|(_, xi)| {
+            if xi.is_null() {
+                Ok(None)
+            } else {
+                geom_ref(&xi).map(|g| Some(g.geom.clone()))
+            }
         })
-        .collect::<Vec<Option<Geometry>>>()
+        .collect::<Result<Vec<Option<Geometry>>, SfConversionError>>()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstar::PointDistance;
+
+    #[test]
+    fn empty_members_are_ignored_by_distance() {
+        let x = Geom::from(GeometryCollection::new_from(vec![
+            Point::new(1.0, 1.0).into(),
+            Polygon::new(LineString::new(vec![]), vec![]).into(),
+        ]));
+
+        assert_eq!(x.distance_2(&[4.0, 5.0]), 25.0);
+
+        let empty = Geom::from(MultiPolygon::new(vec![Polygon::new(
+            LineString::new(vec![]),
+            vec![],
+        )]));
+
+        assert_eq!(empty.distance_2(&[4.0, 5.0]), f64::INFINITY);
+    }
 }
@@ -1,13 +1,651 @@
+//! Typed sfg objects and borrowed views over them
+//!
+//! The structs in this module are checked wrappers around sfg objects. Creating
+//! one with `TryFrom<Robj>` validates the class, storage type, dimension, and
+//! shape of the sfg so that they can be used directly in `#[extendr]` function
+//! signatures. The [`Sfg`] enum accepts any of them.
+//!
+//! The wrappers do not copy their coordinates. They implement the [geo-traits](https://docs.rs/geo-traits)
+//! geometry traits so that geo-traits aware algorithms can operate directly
+//! on the memory owned by R. Coordinates are read from the underlying R
+//! doubles only when they are accessed.
+//!
+//! Each struct records the coordinate dimension of the sfg so that Z and M
+//! ordinates are available through [`CoordTrait::nth()`].
+use crate::{
+    constructors::{as_doubles, as_list, as_matrix},
+    error::SfConversionError,
+    zm::Dim,
+    Geom,
+};
 use extendr_api::prelude::*;
+use geo_traits::{
+    to_geo::{ToGeoLineString, ToGeoMultiLineString, ToGeoMultiPolygon, ToGeoPoint, ToGeoPolygon},
+    CoordTrait, Dimensions, GeometryTrait, GeometryType, LineStringTrait, MultiLineStringTrait,
+    MultiPointTrait, MultiPolygonTrait, PointTrait, PolygonTrait, UnimplementedGeometryCollection,
+    UnimplementedLine, UnimplementedRect, UnimplementedTriangle,
+};
+use std::result::Result;
 
-// TODO impl TryFrom<Sfg> for Robj
-// TODO impl IntoRobj for Sfg
-pub struct SfgPoint(Doubles);
-pub struct SfgMultiPoint(RMatrix<f64>);
+/// A `POINT` sfg
+pub struct SfgPoint(Doubles, Dim);
+/// A `MULTIPOINT` sfg
+pub struct SfgMultiPoint(RMatrix<f64>, Dim);
 
-// LineString 
-pub struct SfgLineString(RMatrix<f64>);
-pub struct SfgMultiLineString(List);
+// LineString
+/// A `LINESTRING` sfg
+pub struct SfgLineString(RMatrix<f64>, Dim);
+/// A `MULTILINESTRING` sfg
+pub struct SfgMultiLineString(List, Dim);
 
-pub struct SfgPolygon(List);
-pub struct SfgMultiPolygon(List);
\ No newline at end of file
+/// A `POLYGON` sfg. Every ring is closed and has at least 4 coordinates.
+pub struct SfgPolygon(List, Dim);
+/// A `MULTIPOLYGON` sfg. Every ring is closed and has at least 4 coordinates.
+pub struct SfgMultiPolygon(List, Dim);
+
+/// Any supported sfg object. `GEOMETRYCOLLECTION`s are not supported.
+pub enum Sfg {
+    Point(SfgPoint),
+    MultiPoint(SfgMultiPoint),
+    LineString(SfgLineString),
+    MultiLineString(SfgMultiLineString),
+    Polygon(SfgPolygon),
+    MultiPolygon(SfgMultiPolygon),
+}
+
+/// A single coordinate read from an sfg object. Unused ordinates are `NaN`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SfgCoord {
+    ords: [f64; 4],
+    dim: Dim,
+}
+
+impl SfgCoord {
+    fn is_empty(&self) -> bool {
+        self.ords[0].is_nan() && self.ords[1].is_nan()
+    }
+}
+
+impl From<Dim> for Dimensions {
+    fn from(dim: Dim) -> Self {
+        match dim {
+            Dim::XY => Dimensions::Xy,
+            Dim::XYZ => Dimensions::Xyz,
+            Dim::XYM => Dimensions::Xym,
+            Dim::XYZM => Dimensions::Xyzm,
+        }
+    }
+}
+
+// read the i-th row of a coordinate matrix
+fn matrix_coord(x: &RMatrix<f64>, i: usize, dim: Dim) -> SfgCoord {
+    let mut ords = [f64::NAN; 4];
+    for (j, oj) in ords.iter_mut().enumerate().take(dim.n_dims()) {
+        *oj = x[[i, j]];
+    }
+    SfgCoord { ords, dim }
+}
+
+// The structure of list elements is validated when the wrappers are
+// created so the conversions below cannot fail. Neither copies any data.
+fn elt_matrix(x: &List, i: usize) -> RMatrix<f64> {
+    RMatrix::try_from(x.elt(i).unwrap()).unwrap()
+}
+
+fn elt_list(x: &List, i: usize) -> List {
+    List::try_from(x.elt(i).unwrap()).unwrap()
+}
+
+// Validation used when creating the wrappers from sfg objects
+fn sfg_dim(x: &Robj, geom_type: &str) -> Result<Dim, SfConversionError> {
+    let cls = x
+        .class()
+        .ok_or(SfConversionError::MissingClass("sfg"))?
+        .collect::<Vec<&str>>();
+
+    if cls.len() < 3 || cls[2] != "sfg" {
+        return Err(SfConversionError::MissingClass("sfg"));
+    }
+
+    if cls[1] != geom_type {
+        return Err(SfConversionError::UnsupportedType(cls[1].to_string()));
+    }
+
+    Dim::from_class(cls[0]).ok_or(SfConversionError::MissingClass("XY"))
+}
+
+fn checked_matrix(x: Robj, dim: Dim) -> Result<RMatrix<f64>, SfConversionError> {
+    let x = as_matrix(x)?;
+    if x.ncols() != dim.n_dims() {
+        return Err(SfConversionError::WrongDimensions {
+            expected: dim.n_dims(),
+            found: x.ncols(),
+        });
+    }
+    Ok(x)
+}
+
+fn checked_lines(x: Robj, dim: Dim) -> Result<List, SfConversionError> {
+    let x = as_list(x)?;
+    for (_, lns) in x.iter() {
+        checked_matrix(lns, dim)?;
+    }
+    Ok(x)
+}
+
+// rings must be closed with at least 4 coordinates
+fn checked_rings(x: Robj, dim: Dim) -> Result<List, SfConversionError> {
+    let x = as_list(x)?;
+    for (_, ring) in x.iter() {
+        let ring = checked_matrix(ring, dim)?;
+        let n = ring.nrows();
+
+        if n < 4 {
+            return Err(SfConversionError::MalformedRing(format!(
+                "rings must have at least 4 coordinates, found {n}"
+            )));
+        }
+
+        // only the ordinates of the dimension are compared as the others are NaN
+        if !(0..dim.n_dims()).all(|j| ring[[0, j]] == ring[[n - 1, j]]) {
+            return Err(SfConversionError::MalformedRing(String::from(
+                "the first and last coordinates of a ring must be identical",
+            )));
+        }
+    }
+    Ok(x)
+}
+
+impl TryFrom<Robj> for SfgPoint {
+    type Error = SfConversionError;
+
+    fn try_from(x: Robj) -> Result<Self, Self::Error> {
+        let dim = sfg_dim(&x, "POINT")?;
+        let x = as_doubles(x)?;
+        if x.len() != dim.n_dims() {
+            return Err(SfConversionError::WrongDimensions {
+                expected: dim.n_dims(),
+                found: x.len(),
+            });
+        }
+        Ok(SfgPoint(x, dim))
+    }
+}
+
+impl TryFrom<Robj> for SfgMultiPoint {
+    type Error = SfConversionError;
+
+    fn try_from(x: Robj) -> Result<Self, Self::Error> {
+        let dim = sfg_dim(&x, "MULTIPOINT")?;
+        Ok(SfgMultiPoint(checked_matrix(x, dim)?, dim))
+    }
+}
+
+impl TryFrom<Robj> for SfgLineString {
+    type Error = SfConversionError;
+
+    fn try_from(x: Robj) -> Result<Self, Self::Error> {
+        let dim = sfg_dim(&x, "LINESTRING")?;
+        Ok(SfgLineString(checked_matrix(x, dim)?, dim))
+    }
+}
+
+impl TryFrom<Robj> for SfgMultiLineString {
+    type Error = SfConversionError;
+
+    fn try_from(x: Robj) -> Result<Self, Self::Error> {
+        let dim = sfg_dim(&x, "MULTILINESTRING")?;
+        Ok(SfgMultiLineString(checked_lines(x, dim)?, dim))
+    }
+}
+
+impl TryFrom<Robj> for SfgPolygon {
+    type Error = SfConversionError;
+
+    fn try_from(x: Robj) -> Result<Self, Self::Error> {
+        let dim = sfg_dim(&x, "POLYGON")?;
+        Ok(SfgPolygon(checked_rings(x, dim)?, dim))
+    }
+}
+
+impl TryFrom<Robj> for SfgMultiPolygon {
+    type Error = SfConversionError;
+
+    fn try_from(x: Robj) -> Result<Self, Self::Error> {
+        let dim = sfg_dim(&x, "MULTIPOLYGON")?;
+        let x = as_list(x)?;
+        for (_, poly) in x.iter() {
+            checked_rings(poly, dim)?;
+        }
+        Ok(SfgMultiPolygon(x, dim))
+    }
+}
+
+impl TryFrom<Robj> for Sfg {
+    type Error = SfConversionError;
+
+    fn try_from(x: Robj) -> Result<Self, Self::Error> {
+        let cls = x
+            .class()
+            .and_then(|mut cls| cls.nth(1))
+            .ok_or(SfConversionError::MissingClass("sfg"))?;
+
+        let res = match cls {
+            "POINT" => Sfg::Point(SfgPoint::try_from(x)?),
+            "MULTIPOINT" => Sfg::MultiPoint(SfgMultiPoint::try_from(x)?),
+            "LINESTRING" => Sfg::LineString(SfgLineString::try_from(x)?),
+            "MULTILINESTRING" => Sfg::MultiLineString(SfgMultiLineString::try_from(x)?),
+            "POLYGON" => Sfg::Polygon(SfgPolygon::try_from(x)?),
+            "MULTIPOLYGON" => Sfg::MultiPolygon(SfgMultiPolygon::try_from(x)?),
+            cls => return Err(SfConversionError::UnsupportedType(cls.to_string())),
+        };
+
+        Ok(res)
+    }
+}
+
+// extendr converts function arguments from `&Robj`. Cloning an Robj
+// does not copy the underlying R object.
+macro_rules! impl_sfg_robj {
+    ($($sfg:ident),*) => {
+        $(
+            impl TryFrom<&Robj> for $sfg {
+                type Error = SfConversionError;
+
+                fn try_from(x: &Robj) -> Result<Self, Self::Error> {
+                    $sfg::try_from(x.clone())
+                }
+            }
+        )*
+    };
+}
+
+impl_sfg_robj!(
+    Sfg,
+    SfgPoint,
+    SfgMultiPoint,
+    SfgLineString,
+    SfgMultiLineString,
+    SfgPolygon,
+    SfgMultiPolygon
+);
+
+// The wrapped objects retain their sfg class
+macro_rules! impl_into_robj {
+    ($($sfg:ident),*) => {
+        $(
+            impl From<$sfg> for Robj {
+                fn from(x: $sfg) -> Self {
+                    x.0.into_robj()
+                }
+            }
+        )*
+    };
+}
+
+impl_into_robj!(
+    SfgPoint,
+    SfgMultiPoint,
+    SfgLineString,
+    SfgMultiLineString,
+    SfgPolygon,
+    SfgMultiPolygon
+);
+
+impl From<Sfg> for Robj {
+    fn from(x: Sfg) -> Self {
+        match x {
+            Sfg::Point(x) => x.into(),
+            Sfg::MultiPoint(x) => x.into(),
+            Sfg::LineString(x) => x.into(),
+            Sfg::MultiLineString(x) => x.into(),
+            Sfg::Polygon(x) => x.into(),
+            Sfg::MultiPolygon(x) => x.into(),
+        }
+    }
+}
+
+impl Sfg {
+    /// The coordinate dimension of the sfg
+    pub fn dim(&self) -> Dim {
+        match self {
+            Sfg::Point(x) => x.1,
+            Sfg::MultiPoint(x) => x.1,
+            Sfg::LineString(x) => x.1,
+            Sfg::MultiLineString(x) => x.1,
+            Sfg::Polygon(x) => x.1,
+            Sfg::MultiPolygon(x) => x.1,
+        }
+    }
+
+    /// Copy the X and Y coordinates into a `Geom`. `POINT EMPTY`, and `NA`
+    /// rows of a `MULTIPOINT`, are a `Point` with `NaN` coordinates.
+    pub fn to_geom(&self) -> Geom {
+        match self {
+            Sfg::Point(x) => x
+                .try_to_point()
+                .unwrap_or(geo_types::Point::new(f64::NAN, f64::NAN))
+                .into(),
+            Sfg::MultiPoint(x) => {
+                let pnts = x
+                    .points()
+                    .map(|p| {
+                        p.try_to_point()
+                            .unwrap_or(geo_types::Point::new(f64::NAN, f64::NAN))
+                    })
+                    .collect();
+                geo_types::MultiPoint::new(pnts).into()
+            }
+            Sfg::LineString(x) => x.to_line_string().into(),
+            Sfg::MultiLineString(x) => x.to_multi_line_string().into(),
+            Sfg::Polygon(x) => x.to_polygon().into(),
+            Sfg::MultiPolygon(x) => x.to_multi_polygon().into(),
+        }
+    }
+
+    fn as_geometry_type(
+        &self,
+    ) -> GeometryType<
+        '_,
+        SfgPoint,
+        SfgLineString,
+        SfgPolygon,
+        SfgMultiPoint,
+        SfgMultiLineString,
+        SfgMultiPolygon,
+        UnimplementedGeometryCollection<f64>,
+        UnimplementedRect<f64>,
+        UnimplementedTriangle<f64>,
+        UnimplementedLine<f64>,
+    > {
+        match self {
+            Sfg::Point(x) => GeometryType::Point(x),
+            Sfg::MultiPoint(x) => GeometryType::MultiPoint(x),
+            Sfg::LineString(x) => GeometryType::LineString(x),
+            Sfg::MultiLineString(x) => GeometryType::MultiLineString(x),
+            Sfg::Polygon(x) => GeometryType::Polygon(x),
+            Sfg::MultiPolygon(x) => GeometryType::MultiPolygon(x),
+        }
+    }
+}
+
+// geo-traits implementations
+impl CoordTrait for SfgCoord {
+    type T = f64;
+
+    fn dim(&self) -> Dimensions {
+        self.dim.into()
+    }
+
+    fn x(&self) -> Self::T {
+        self.ords[0]
+    }
+
+    fn y(&self) -> Self::T {
+        self.ords[1]
+    }
+
+    fn nth_or_panic(&self, n: usize) -> Self::T {
+        if n >= self.dim.n_dims() {
+            panic!("{} coordinates do not have ordinate {n}", self.dim.as_str())
+        }
+        self.ords[n]
+    }
+}
+
+impl PointTrait for SfgCoord {
+    type CoordType<'a>
+        = SfgCoord
+    where
+        Self: 'a;
+
+    fn coord(&self) -> Option<Self::CoordType<'_>> {
+        (!self.is_empty()).then_some(*self)
+    }
+}
+
+impl PointTrait for SfgPoint {
+    type CoordType<'a>
+        = SfgCoord
+    where
+        Self: 'a;
+
+    // POINT EMPTY has NA coordinates
+    fn coord(&self) -> Option<Self::CoordType<'_>> {
+        let mut ords = [f64::NAN; 4];
+        for (j, oj) in ords.iter_mut().enumerate().take(self.1.n_dims()) {
+            *oj = self.0[j].inner();
+        }
+
+        let crd = SfgCoord { ords, dim: self.1 };
+        (!crd.is_empty()).then_some(crd)
+    }
+}
+
+impl MultiPointTrait for SfgMultiPoint {
+    type InnerPointType<'a>
+        = SfgCoord
+    where
+        Self: 'a;
+
+    fn num_points(&self) -> usize {
+        self.0.nrows()
+    }
+
+    unsafe fn point_unchecked(&self, i: usize) -> Self::InnerPointType<'_> {
+        matrix_coord(&self.0, i, self.1)
+    }
+}
+
+impl LineStringTrait for SfgLineString {
+    type CoordType<'a>
+        = SfgCoord
+    where
+        Self: 'a;
+
+    fn num_coords(&self) -> usize {
+        self.0.nrows()
+    }
+
+    unsafe fn coord_unchecked(&self, i: usize) -> Self::CoordType<'_> {
+        matrix_coord(&self.0, i, self.1)
+    }
+}
+
+impl MultiLineStringTrait for SfgMultiLineString {
+    type InnerLineStringType<'a>
+        = SfgLineString
+    where
+        Self: 'a;
+
+    fn num_line_strings(&self) -> usize {
+        self.0.len()
+    }
+
+    unsafe fn line_string_unchecked(&self, i: usize) -> Self::InnerLineStringType<'_> {
+        SfgLineString(elt_matrix(&self.0, i), self.1)
+    }
+}
+
+impl PolygonTrait for SfgPolygon {
+    type RingType<'a>
+        = SfgLineString
+    where
+        Self: 'a;
+
+    // POLYGON EMPTY has no rings
+    fn exterior(&self) -> Option<Self::RingType<'_>> {
+        if self.0.len() == 0 {
+            None
+        } else {
+            Some(SfgLineString(elt_matrix(&self.0, 0), self.1))
+        }
+    }
+
+    fn num_interiors(&self) -> usize {
+        self.0.len().saturating_sub(1)
+    }
+
+    unsafe fn interior_unchecked(&self, i: usize) -> Self::RingType<'_> {
+        SfgLineString(elt_matrix(&self.0, i + 1), self.1)
+    }
+}
+
+impl MultiPolygonTrait for SfgMultiPolygon {
+    type InnerPolygonType<'a>
+        = SfgPolygon
+    where
+        Self: 'a;
+
+    fn num_polygons(&self) -> usize {
+        self.0.len()
+    }
+
+    unsafe fn polygon_unchecked(&self, i: usize) -> Self::InnerPolygonType<'_> {
+        SfgPolygon(elt_list(&self.0, i), self.1)
+    }
+}
+
+// Every geo-traits geometry must also implement `GeometryTrait`.
+// `$point` is the point type of the geometry and `$variant` the
+// `GeometryType` it is matched as. Alternatively `fn $as_type` is a
+// method which determines the `GeometryType`.
+macro_rules! impl_geometry_trait {
+    ($sfg:ident, $point:ident, fn $as_type:ident) => {
+        impl GeometryTrait for $sfg {
+            type T = f64;
+            type PointType<'b>
+                = $point
+            where
+                Self: 'b;
+            type LineStringType<'b>
+                = SfgLineString
+            where
+                Self: 'b;
+            type PolygonType<'b>
+                = SfgPolygon
+            where
+                Self: 'b;
+            type MultiPointType<'b>
+                = SfgMultiPoint
+            where
+                Self: 'b;
+            type MultiLineStringType<'b>
+                = SfgMultiLineString
+            where
+                Self: 'b;
+            type MultiPolygonType<'b>
+                = SfgMultiPolygon
+            where
+                Self: 'b;
+            type GeometryCollectionType<'b>
+                = UnimplementedGeometryCollection<f64>
+            where
+                Self: 'b;
+            type RectType<'b>
+                = UnimplementedRect<f64>
+            where
+                Self: 'b;
+            type TriangleType<'b>
+                = UnimplementedTriangle<f64>
+            where
+                Self: 'b;
+            type LineType<'b>
+                = UnimplementedLine<f64>
+            where
+                Self: 'b;
+
+            fn dim(&self) -> Dimensions {
+                self.sfg_dim().into()
+            }
+
+            fn as_type(
+                &self,
+            ) -> GeometryType<
+                '_,
+                Self::PointType<'_>,
+                Self::LineStringType<'_>,
+                Self::PolygonType<'_>,
+                Self::MultiPointType<'_>,
+                Self::MultiLineStringType<'_>,
+                Self::MultiPolygonType<'_>,
+                Self::GeometryCollectionType<'_>,
+                Self::RectType<'_>,
+                Self::TriangleType<'_>,
+                Self::LineType<'_>,
+            > {
+                self.$as_type()
+            }
+        }
+    };
+    ($sfg:ident, $point:ident, $variant:ident) => {
+        impl_geometry_trait!($sfg, $point, fn as_single_type);
+
+        impl $sfg {
+            fn as_single_type(
+                &self,
+            ) -> GeometryType<
+                '_,
+                $point,
+                SfgLineString,
+                SfgPolygon,
+                SfgMultiPoint,
+                SfgMultiLineString,
+                SfgMultiPolygon,
+                UnimplementedGeometryCollection<f64>,
+                UnimplementedRect<f64>,
+                UnimplementedTriangle<f64>,
+                UnimplementedLine<f64>,
+            > {
+                GeometryType::$variant(self)
+            }
+        }
+    };
+}
+
+impl_geometry_trait!(Sfg, SfgPoint, fn as_geometry_type);
+impl_geometry_trait!(SfgCoord, SfgCoord, Point);
+impl_geometry_trait!(SfgPoint, SfgPoint, Point);
+impl_geometry_trait!(SfgMultiPoint, SfgPoint, MultiPoint);
+impl_geometry_trait!(SfgLineString, SfgPoint, LineString);
+impl_geometry_trait!(SfgMultiLineString, SfgPoint, MultiLineString);
+impl_geometry_trait!(SfgPolygon, SfgPoint, Polygon);
+impl_geometry_trait!(SfgMultiPolygon, SfgPoint, MultiPolygon);
+
+// The coordinate dimension of each wrapper
+trait SfgDim {
+    fn sfg_dim(&self) -> Dim;
+}
+
+impl SfgDim for SfgCoord {
+    fn sfg_dim(&self) -> Dim {
+        self.dim
+    }
+}
+
+impl SfgDim for Sfg {
+    fn sfg_dim(&self) -> Dim {
+        self.dim()
+    }
+}
+
+macro_rules! impl_sfg_dim {
+    ($($sfg:ident),*) => {
+        $(
+            impl SfgDim for $sfg {
+                fn sfg_dim(&self) -> Dim {
+                    self.1
+                }
+            }
+        )*
+    };
+}
+
+impl_sfg_dim!(
+    SfgPoint,
+    SfgMultiPoint,
+    SfgLineString,
+    SfgMultiLineString,
+    SfgPolygon,
+    SfgMultiPolygon
+);
@@ -0,0 +1,203 @@
+//! Convert spatial query results to and from sf's sparse geometry binary predicates
+//!
+//! sf returns the result of binary predicates such as `sf::st_intersects()` as
+//! an `sgbp` object. It is a list with one integer vector per geometry of `x`
+//! containing the 1-based indices of the geometries of `y` for which the
+//! predicate is true. It has the attributes:
+//!
+//! - `predicate`: the name of the predicate e.g. `"intersects"`
+//! - `region.id`: the row names of `x` as a character vector
+//! - `ncol`: the number of geometries in `y`
+//! - `remove_self` and `retain_unique`: always `FALSE` here
+//!
+//! [`Sgbp`] stores 0-based indices such as those returned by
+//! [`SpatialIndex::candidates()`](crate::index::SpatialIndex::candidates) and
+//! converts them to and from 1-based R indices.
+use crate::error::SfConversionError;
+use extendr_api::prelude::*;
+use std::result::Result;
+
+/// A sparse geometry binary predicate
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Sgbp {
+    /// the 0-based indices of `y` for each geometry of `x`
+    pub indices: Vec<Vec<usize>>,
+    /// the number of geometries in `y`
+    pub ncol: usize,
+    /// the name of the predicate e.g. `"intersects"`
+    pub predicate: String,
+    /// the row names of `x`
+    pub region_id: Vec<String>,
+}
+
+impl Sgbp {
+    /// Create an `Sgbp` from the 0-based indices of `y` for each geometry of `x`.
+    /// The `region_id` is the row number of each geometry of `x`.
+    pub fn new(indices: Vec<Vec<usize>>, ncol: usize, predicate: &str) -> Self {
+        let region_id = (1..=indices.len()).map(|i| i.to_string()).collect();
+        Sgbp {
+            indices,
+            ncol,
+            predicate: predicate.to_string(),
+            region_id,
+        }
+    }
+
+    /// Create an R `sgbp` object with 1-based indices. Every index must be
+    /// less than `ncol` and there must be a `region_id` for every element.
+    pub fn to_robj(&self) -> Result<Robj, SfConversionError> {
+        if self.region_id.len() != self.indices.len() {
+            return Err(SfConversionError::LengthMismatch {
+                expected: self.indices.len(),
+                found: self.region_id.len(),
+            });
+        }
+
+        let values = self
+            .indices
+            .iter()
+            .map(|xi| {
+                xi.iter()
+                    .map(|i| {
+                        if *i >= self.ncol {
+                            return Err(SfConversionError::InvalidIndex(format!(
+                                "{} is out of bounds for {} geometries",
+                                i + 1,
+                                self.ncol
+                            )));
+                        }
+                        Ok(*i as i32 + 1)
+                    })
+                    .collect::<Result<Vec<i32>, SfConversionError>>()
+                    .map(Robj::from)
+            })
+            .collect::<Result<Vec<Robj>, SfConversionError>>()?;
+
+        let res = List::from_values(values)
+            .into_robj()
+            .set_attrib("predicate", self.predicate.as_str())
+            .unwrap()
+            .set_attrib("region.id", self.region_id.clone())
+            .unwrap()
+            .set_attrib("remove_self", false)
+            .unwrap()
+            .set_attrib("retain_unique", false)
+            .unwrap()
+            .set_attrib("ncol", self.ncol as i32)
+            .unwrap()
+            .set_class(["sgbp", "list"])
+            .unwrap()
+            .clone();
+
+        Ok(res)
+    }
+}
+
+/// Read an R `sgbp` object converting its indices to 0-based indices.
+/// A missing `region.id` attribute is the row number and a missing `ncol`
+/// attribute is the largest index.
+impl TryFrom<Robj> for Sgbp {
+    type Error = SfConversionError;
+
+    fn try_from(x: Robj) -> Result<Self, Self::Error> {
+        if !x.inherits("sgbp") {
+            return Err(SfConversionError::MissingClass("sgbp"));
+        }
+
+        let list =
+            List::try_from(x.clone()).map_err(|_| SfConversionError::bad_storage("list", &x))?;
+
+        let indices = list
+            .iter()
+            .map(|(_, xi)| {
+                let ints = Integers::try_from(xi.clone())
+                    .map_err(|_| SfConversionError::bad_storage("integer", &xi))?;
+
+                ints.iter()
+                    .map(sgbp_index)
+                    .collect::<Result<Vec<usize>, SfConversionError>>()
+            })
+            .collect::<Result<Vec<Vec<usize>>, SfConversionError>>()?;
+
+        let max_index = indices.iter().flatten().max().map_or(0, |i| i + 1);
+        let ncol = match x.get_attrib("ncol") {
+            Some(ncol) => sgbp_ncol(&ncol)?,
+            None => max_index,
+        };
+
+        if max_index > ncol {
+            return Err(SfConversionError::InvalidIndex(format!(
+                "{max_index} is out of bounds for {ncol} geometries"
+            )));
+        }
+
+        let predicate = x
+            .get_attrib("predicate")
+            .and_then(|p| p.as_str().map(String::from))
+            .unwrap_or_default();
+
+        let region_id = match x.get_attrib("region.id") {
+            Some(ids) => Strings::try_from(ids.clone())
+                .map_err(|_| SfConversionError::bad_storage("character", &ids))?
+                .iter()
+                .map(|id| id.as_str().to_string())
+                .collect(),
+            None => (1..=indices.len()).map(|i| i.to_string()).collect(),
+        };
+
+        Ok(Sgbp {
+            indices,
+            ncol,
+            predicate,
+            region_id,
+        })
+    }
+}
+
+/// Create an R `sgbp` object from the 0-based indices of `y` for each geometry
+/// of `x`. `ncol` is the number of geometries in `y`.
+pub fn to_sgbp(
+    x: Vec<Vec<usize>>,
+    ncol: usize,
+    predicate: &str,
+) -> Result<Robj, SfConversionError> {
+    Sgbp::new(x, ncol, predicate).to_robj()
+}
+
+/// Read the 0-based indices of an R `sgbp` object
+pub fn sgbp_to_indices(x: Robj) -> Result<Vec<Vec<usize>>, SfConversionError> {
+    Ok(Sgbp::try_from(x)?.indices)
+}
+
+// a 1-based R index to a 0-based index
+fn sgbp_index(x: Rint) -> Result<usize, SfConversionError> {
+    if x.is_na() {
+        return Err(SfConversionError::InvalidIndex("`NA` index".to_string()));
+    }
+
+    match x.inner() {
+        i if i < 1 => Err(SfConversionError::InvalidIndex(format!(
+            "{i} is not a positive index"
+        ))),
+        i => Ok(i as usize - 1),
+    }
+}
+
+// the `ncol` attribute as an integer or double count
+fn sgbp_ncol(x: &Robj) -> Result<usize, SfConversionError> {
+    // NA_integer_ is i32::MIN and NA_real_ is NaN
+    let ncol = match (x.as_integer_slice(), x.as_real_slice()) {
+        (Some([i32::MIN]), _) => f64::NAN,
+        (Some([n]), _) => *n as f64,
+        (_, Some([n])) => *n,
+        _ => return Err(SfConversionError::bad_storage("integer", x)),
+    };
+
+    if !ncol.is_finite() || ncol < 0.0 || ncol.fract() != 0.0 {
+        return Err(SfConversionError::InvalidIndex(format!(
+            "`ncol` must be a non-negative count, found {ncol}"
+        )));
+    }
+
+    Ok(ncol as usize)
+}
@@ -1,36 +1,165 @@
 //! Conversion from geo-types to {sf} type objects
 //!
 //! Provides simple conversion from `Geom` wrapper struct to an sfg class object.
-//! Additionally provides the ability to convert from `Vec<Option<Geom>>` to a list
-//! of sfg objects that can be easily converted into an sfc object by running `sf::st_sfc()`.
+//! Additionally provides the ability to convert from `Vec<Option<Geom>>` to a
+//! complete `sfc` object with `to_sfc()` or to a bare list of sfg objects with
+//! `geoms_to_sfc()`. Whole sf data.frames are created with `to_sf()`.
 //!
-use crate::Geom;
+use crate::{
+    crs::Crs,
+    error::SfConversionError,
+    fromsf::SfDataFrame,
+    zm::{GeomZM, OrdinateWriter},
+    Geom,
+};
 /// Takes a single Geom struct and creates the corresponding `sfg` object
 use extendr_api::prelude::*;
 use extendr_api::Robj;
+use geo::CoordsIter;
 use geo_types::*;
+use rstar::Envelope;
+use std::result::Result;
 
 /// A general purpose function that matches on the `Geometry` enum to convert into the
-/// appropriate sfg object type. If the Geom cannot be matched (e.g. Line or Triangle),
-/// it will return a `NULL` Robj.
+/// appropriate sfg object type. `Line` is written as a `LINESTRING` whereas `Rect` and
+/// `Triangle` are written as a `POLYGON`. Empty geometries are written as empty sfg objects.
 pub fn to_sfg(x: Geom) -> Robj {
-    let x = x.geom;
+    geometry_sfg(x.geom, &mut OrdinateWriter::xy())
+}
+
+/// Takes a `Vec<Option<Geom>>` such as the result of `sfc_to_geoms()` and creates
+/// a complete `sfc` object. The `sfc_{TYPE}` class, `bbox`, `crs`, `precision`,
+/// and `n_empty` attributes are set. When geometry types are mixed the class is
+/// `sfc_GEOMETRY` and the `classes` attribute records the type of each geometry.
+///
+/// sfc objects cannot contain `NULL` geometries. As in `sf::st_sfc()`, missing
+/// geometries are written as empty geometries of the sfc type, or
+/// `GEOMETRYCOLLECTION EMPTY` when the types are mixed.
+///
+/// The `crs` attribute is unknown (`NA`). Use `to_sfc_with_crs()` to set it.
+pub fn to_sfc(x: Vec<Option<Geom>>) -> Robj {
+    to_sfc_with_crs(x, &Crs::default())
+}
+
+/// Like `to_sfc()` but sets the `crs` attribute from a `Crs` such as the one
+/// returned by [`sfc_to_geoms_crs()`](crate::fromsf::sfc_to_geoms_crs).
+pub fn to_sfc_with_crs(x: Vec<Option<Geom>>, crs: &Crs) -> Robj {
+    let types = x
+        .iter()
+        .map(|geo| geo.as_ref().map(|g| sfg_type(&g.geom)))
+        .collect::<Vec<Option<&str>>>();
+
+    let mut present = types.iter().flatten();
+    let uniform = match present.next() {
+        Some(first) => present.all(|typ| typ == first).then_some(*first),
+        None => None,
+    };
+
+    let fill_type = uniform.unwrap_or("GEOMETRYCOLLECTION");
+    let classes = types
+        .iter()
+        .map(|typ| typ.unwrap_or(fill_type))
+        .collect::<Vec<&str>>();
+
+    let sfc_type = match classes.first() {
+        Some(first) if classes.iter().all(|typ| typ == first) => *first,
+        _ => "GEOMETRY",
+    };
+
+    let bbox = sfc_bbox(&x);
+    let n_empty = x
+        .iter()
+        .filter(|geo| geo.as_ref().map_or(true, |g| g.is_empty()))
+        .count() as i32;
+
+    let mut res = x
+        .into_iter()
+        .map(|geo| match geo {
+            Some(geo) => to_sfg(geo),
+            None => empty_sfg(fill_type),
+        })
+        .collect::<List>();
+
+    if sfc_type == "GEOMETRY" {
+        res.set_attrib("classes", classes).unwrap();
+    }
+
+    res.set_attrib("precision", 0.0)
+        .unwrap()
+        .set_attrib("bbox", bbox)
+        .unwrap()
+        .set_attrib("crs", crs)
+        .unwrap()
+        .set_attrib("n_empty", n_empty)
+        .unwrap()
+        .set_class([format!("sfc_{sfc_type}"), String::from("sfc")])
+        .unwrap()
+        .clone()
+        .into()
+}
+
+/// The sfg geometry type of a `Geometry` e.g. `"POINT"`. `Line` is represented
+/// as a `"LINESTRING"` whereas `Rect` and `Triangle` are a `"POLYGON"`.
+pub fn sfg_type(x: &Geometry) -> &'static str {
     match x {
-        Geometry::Point(x) => from_point(x),
-        Geometry::MultiPoint(x) => from_multipoint(x),
-        Geometry::LineString(x) => from_linestring(x),
-        Geometry::MultiLineString(x) => from_multilinestring(x),
-        Geometry::Polygon(x) => from_polygon(x),
-        Geometry::MultiPolygon(x) => from_multipolygon(x),
-        _ => Robj::from(NULL),
+        Geometry::Point(_) => "POINT",
+        Geometry::MultiPoint(_) => "MULTIPOINT",
+        Geometry::Line(_) | Geometry::LineString(_) => "LINESTRING",
+        Geometry::MultiLineString(_) => "MULTILINESTRING",
+        Geometry::Polygon(_) | Geometry::Rect(_) | Geometry::Triangle(_) => "POLYGON",
+        Geometry::MultiPolygon(_) => "MULTIPOLYGON",
+        Geometry::GeometryCollection(_) => "GEOMETRYCOLLECTION",
     }
 }
 
+// Calculates the `bbox` attribute of an sfc. Envelopes follow the same policy
+// as the spatial index, see `Geom::to_aabb()`, so `NaN` coordinates are
+// ignored. If no geometry has an envelope the bounding box is all `NA`.
+fn sfc_bbox(x: &[Option<Geom>]) -> Robj {
+    let bbox = x
+        .iter()
+        .flatten()
+        .filter_map(|geo| geo.to_aabb())
+        .reduce(|mut acc, env| {
+            acc.merge(&env);
+            acc
+        });
+
+    let vals = match bbox {
+        Some(env) => [
+            env.lower()[0],
+            env.lower()[1],
+            env.upper()[0],
+            env.upper()[1],
+        ],
+        None => [Rfloat::na().inner(); 4],
+    };
+
+    Robj::from(vals.to_vec())
+        .set_names(["xmin", "ymin", "xmax", "ymax"])
+        .unwrap()
+        .set_class(["bbox"])
+        .unwrap()
+        .clone()
+}
+
+/// Creates an empty `XY` sfg of the given geometry type such as `"POLYGON"`.
+/// Empty geometries follow the sf encoding: `POINT EMPTY` is `c(NA, NA)`,
+/// `MULTIPOINT` and `LINESTRING` are zero row matrices, and all other
+/// types are empty lists.
+pub fn empty_sfg(cls: &str) -> Robj {
+    let mut res = match cls {
+        "POINT" => Robj::from(vec![Rfloat::na().inner(); 2]),
+        "MULTIPOINT" | "LINESTRING" => Robj::from(RMatrix::<f64>::new_matrix(0, 2, |_, _| 0.0)),
+        _ => Robj::from(List::new(0)),
+    };
+
+    res.set_class(["XY", cls, "sfg"]).unwrap().clone()
+}
+
 /// Takes a `Vec<Option<Geom>>` such as the result of `sfc_to_geometry()`
-/// and creates a list of sfg objects. This can be easily turned into an `sfc`
-/// by passing the results to `sf::st_sfc()`. This cannot be converted into an
-/// `sfc` object without first calculating the bounding box which would require
-/// importing geo.
+/// and creates a list of sfg objects. Missing geometries are `NULL`. Use
+/// `to_sfc()` to create a complete `sfc` object instead.
 pub fn geoms_to_sfc(x: Vec<Option<Geom>>) -> List {
     //let cls = determine_sfc_class(&x).to_ascii_uppercase();
     // let cls_array = [format!("sfc_{cls}"), String::from("sfc")];
@@ -43,6 +172,69 @@ pub fn geoms_to_sfc(x: Vec<Option<Geom>>) -> List {
         .collect::<List>()
 }
 
+/// Create an sf data.frame from an [`SfDataFrame`]. The attribute columns are
+/// followed by the geometry column named `sf_column`. The `sf_column` and `agr`
+/// attributes are set as in `sf::st_sf()`.
+///
+/// Each attribute column must have one element per geometry and no attribute
+/// column may be named `sf_column`.
+pub fn to_sf(x: SfDataFrame) -> Result<Robj, SfConversionError> {
+    let n_rows = x.geoms.len();
+
+    for (name, col) in x.columns.iter() {
+        if *name == x.sf_column {
+            return Err(SfConversionError::DuplicateColumn(name.clone()));
+        }
+
+        if col.len() != n_rows {
+            return Err(SfConversionError::LengthMismatch {
+                expected: n_rows,
+                found: col.len(),
+            });
+        }
+    }
+
+    let agr_codes = x.agr.iter().map(|agr| match agr.as_deref() {
+        Some("constant") => Rint::from(1),
+        Some("aggregate") => Rint::from(2),
+        Some("identity") => Rint::from(3),
+        _ => Rint::na(),
+    });
+
+    let (mut names, mut values): (Vec<String>, Vec<Robj>) = x.columns.into_iter().unzip();
+
+    let agr = Integers::from_values(agr_codes)
+        .into_robj()
+        .set_attrib("levels", ["constant", "aggregate", "identity"])
+        .unwrap()
+        .set_class(["factor"])
+        .unwrap()
+        .set_names(names.clone())
+        .unwrap()
+        .clone();
+
+    names.push(x.sf_column.clone());
+    values.push(to_sfc_with_crs(x.geoms, &x.crs));
+
+    let res = List::from_names_and_values(names, values)
+        .unwrap()
+        .into_robj()
+        .set_attrib(
+            "row.names",
+            Integers::from_values([Rint::na(), Rint::from(-(n_rows as i32))]),
+        )
+        .unwrap()
+        .set_attrib("sf_column", x.sf_column.as_str())
+        .unwrap()
+        .set_attrib("agr", agr)
+        .unwrap()
+        .set_class(["sf", "data.frame"])
+        .unwrap()
+        .clone();
+
+    Ok(res)
+}
+
 /// Utility function to identify the class of an sfc object .
 pub fn determine_sfc_class(x: &Vec<Option<Geom>>) -> String {
     let mut result = String::new();
@@ -64,82 +256,191 @@ pub fn determine_sfc_class(x: &Vec<Option<Geom>>) -> String {
     result
 }
 
-fn from_coord(x: Coord) -> [f64; 2] {
-    [x.x, x.y]
-}
-
 /// Convert a `Point` to a sfg
 pub fn from_point(x: Point) -> Robj {
-    let x = from_coord(x.0);
-    Robj::try_from(x)
-        .unwrap()
-        .set_class(["XY", "POINT", "sfg"])
-        .unwrap()
-        .clone()
+    point_sfg(x, &mut OrdinateWriter::xy())
 }
 
 /// Convert a `MultiPoint` to an sfg
 pub fn from_multipoint(x: MultiPoint) -> Robj {
-    let x = x
-        .into_iter()
-        .map(|p| from_coord(p.into()))
-        .collect::<Vec<[f64; 2]>>();
+    multipoint_sfg(x, &mut OrdinateWriter::xy())
+}
 
-    let res = RMatrix::new_matrix(x.len(), 2, |r, c| x[r][c]);
-    Robj::from(res)
-        .set_class(["XY", "MULTIPOINT", "sfg"])
+/// Convert a `LineString` to an sfg
+pub fn from_linestring(x: LineString) -> Robj {
+    linestring_sfg(x, &mut OrdinateWriter::xy())
+}
+
+/// Convert a `MultiLineString` to an sfg
+pub fn from_multilinestring(x: MultiLineString) -> Robj {
+    multilinestring_sfg(x, &mut OrdinateWriter::xy())
+}
+
+/// Convert a `Polygon` to an sfg
+pub fn from_polygon(x: Polygon) -> Robj {
+    polygon_sfg(x, &mut OrdinateWriter::xy())
+}
+
+/// Convert a `MultiPolygon` to an sfg
+pub fn from_multipolygon(x: MultiPolygon) -> Robj {
+    multipolygon_sfg(x, &mut OrdinateWriter::xy())
+}
+
+/// Convert a `GeometryCollection` to an sfg. Nested collections are
+/// converted recursively.
+pub fn from_geometrycollection(x: GeometryCollection) -> Robj {
+    geometrycollection_sfg(x, &mut OrdinateWriter::xy())
+}
+
+/// Convert a `Line` to a `LINESTRING` sfg
+pub fn from_line(x: Line) -> Robj {
+    line_sfg(x, &mut OrdinateWriter::xy())
+}
+
+/// Convert a `Rect` to a `POLYGON` sfg
+pub fn from_rect(x: Rect) -> Robj {
+    corners_sfg(x.coords_iter(), &mut OrdinateWriter::xy())
+}
+
+/// Convert a `Triangle` to a `POLYGON` sfg
+pub fn from_triangle(x: Triangle) -> Robj {
+    corners_sfg(x.coords_iter(), &mut OrdinateWriter::xy())
+}
+
+/// Convert a `GeomZM` to an sfg with the appropriate `"XYZ"`, `"XYM"`, or
+/// `"XYZM"` dimension class. Any Z or M values missing from the `GeomZM`
+/// are written as `NA`.
+pub fn to_sfg_zm(x: GeomZM) -> Robj {
+    let mut ords = OrdinateWriter::new(x.dim, &x.z, &x.m);
+    geometry_sfg(x.geom.geom, &mut ords)
+}
+
+// The functions below write sfg objects while consuming Z and M ordinates
+// in coordinate order. For XY geometries the writer only holds X and Y.
+fn geometry_sfg(x: Geometry, ords: &mut OrdinateWriter) -> Robj {
+    match x {
+        Geometry::Point(x) => point_sfg(x, ords),
+        Geometry::MultiPoint(x) => multipoint_sfg(x, ords),
+        Geometry::LineString(x) => linestring_sfg(x, ords),
+        Geometry::MultiLineString(x) => multilinestring_sfg(x, ords),
+        Geometry::Polygon(x) => polygon_sfg(x, ords),
+        Geometry::MultiPolygon(x) => multipolygon_sfg(x, ords),
+        Geometry::GeometryCollection(x) => geometrycollection_sfg(x, ords),
+        Geometry::Line(x) => line_sfg(x, ords),
+        Geometry::Rect(x) => corners_sfg(x.coords_iter(), ords),
+        Geometry::Triangle(x) => corners_sfg(x.coords_iter(), ords),
+    }
+}
+
+// A `Rect` or `Triangle` is written as a POLYGON from its corners so that
+// their Z and M values line up. See `OrdinateWriter::closed_ring()`.
+fn corners_sfg(x: impl Iterator<Item = Coord>, ords: &mut OrdinateWriter) -> Robj {
+    let rows = ords.closed_ring(x);
+    let ring = Robj::from(ords.rows_matrix(rows));
+
+    List::from_values([ring])
+        .into_robj()
+        .set_class([ords.dim.as_str(), "POLYGON", "sfg"])
         .unwrap()
         .clone()
 }
 
-/// Convert a `LineString` to an sfg
-pub fn from_linestring(x: LineString) -> Robj {
-    let x = x.into_iter().map(from_coord).collect::<Vec<[f64; 2]>>();
+fn point_sfg(x: Point, ords: &mut OrdinateWriter) -> Robj {
+    let mut x = ords.row(x.0);
+
+    // POINT EMPTY is written with NA_real_ rather than NaN
+    if x[0].is_nan() && x[1].is_nan() {
+        x.iter_mut().for_each(|xi| *xi = Rfloat::na().inner());
+    }
+
+    Robj::from(x)
+        .set_class([ords.dim.as_str(), "POINT", "sfg"])
+        .unwrap()
+        .clone()
+}
 
-    let res = RMatrix::new_matrix(x.len(), 2, |r, c| x[r][c]);
+fn multipoint_sfg(x: MultiPoint, ords: &mut OrdinateWriter) -> Robj {
+    let res = ords.matrix(x.into_iter().map(|p| p.0));
     Robj::from(res)
-        .set_class(["XY", "LINESTRING", "sfg"])
+        .set_class([ords.dim.as_str(), "MULTIPOINT", "sfg"])
         .unwrap()
         .clone()
 }
 
-/// Convert a `MultiLineString` to an sfg
-pub fn from_multilinestring(x: MultiLineString) -> Robj {
+// an unclassed coordinate matrix used by linestrings and rings
+fn coords_matrix(x: LineString, ords: &mut OrdinateWriter) -> Robj {
+    Robj::from(ords.matrix(x.into_iter()))
+}
+
+fn linestring_sfg(x: LineString, ords: &mut OrdinateWriter) -> Robj {
+    coords_matrix(x, ords)
+        .set_class([ords.dim.as_str(), "LINESTRING", "sfg"])
+        .unwrap()
+        .clone()
+}
+
+fn line_sfg(x: Line, ords: &mut OrdinateWriter) -> Robj {
+    linestring_sfg(LineString::from(x), ords)
+}
+
+fn multilinestring_sfg(x: MultiLineString, ords: &mut OrdinateWriter) -> Robj {
     x.0.into_iter()
-        .map(from_linestring)
+        .map(|lns| coords_matrix(lns, ords))
         .collect::<List>()
         .into_robj()
-        .set_class(["XY", "MULTILINESTRING", "sfg"])
+        .set_class([ords.dim.as_str(), "MULTILINESTRING", "sfg"])
         .unwrap()
         .clone()
 }
 
-/// Convert a `Polygon` to an sfg
-pub fn from_polygon(x: Polygon) -> Robj {
-    let exterior = x.exterior().to_owned();
-    let interriors = x.interiors().to_owned();
-
+// an unclassed list of rings used by polygons and multipolygons
+fn polygon_rings(x: Polygon, ords: &mut OrdinateWriter) -> List {
     // combine the exterior ring and interrior rings into 1 vector first
     // then iterate through them.
     // no method to go from Polygon to multilinestring
-    let mut res: Vec<LineString> = Vec::with_capacity(interriors.len() + 1);
+    let (exterior, interiors) = x.into_inner();
+
+    // POLYGON EMPTY is an empty list
+    if exterior.0.is_empty() && interiors.is_empty() {
+        return List::new(0);
+    }
+
+    let mut res: Vec<LineString> = Vec::with_capacity(interiors.len() + 1);
     res.push(exterior);
-    res.extend(interriors.into_iter());
+    res.extend(interiors);
 
-    let res = res.into_iter().map(from_linestring).collect::<Vec<Robj>>();
+    res.into_iter()
+        .map(|ring| coords_matrix(ring, ords))
+        .collect::<List>()
+}
 
-    Robj::from(List::from_values(res))
-        .set_class(["XY", "POLYGON", "sfg"])
+fn polygon_sfg(x: Polygon, ords: &mut OrdinateWriter) -> Robj {
+    Robj::from(polygon_rings(x, ords))
+        .set_class([ords.dim.as_str(), "POLYGON", "sfg"])
         .unwrap()
         .clone()
 }
 
-/// Convert a `MultiPolygon` to an sfg
-pub fn from_multipolygon(x: MultiPolygon) -> Robj {
-    let res = x.into_iter().map(from_polygon).collect::<List>();
+fn multipolygon_sfg(x: MultiPolygon, ords: &mut OrdinateWriter) -> Robj {
+    let res = x
+        .into_iter()
+        .map(|poly| polygon_rings(poly, ords))
+        .collect::<List>();
+
+    Robj::from(res)
+        .set_class([ords.dim.as_str(), "MULTIPOLYGON", "sfg"])
+        .unwrap()
+        .clone()
+}
+
+fn geometrycollection_sfg(x: GeometryCollection, ords: &mut OrdinateWriter) -> Robj {
+    let res = x
+        .into_iter()
+        .map(|geo| geometry_sfg(geo, ords))
+        .collect::<List>();
 
     Robj::from(res)
-        .set_class(["XY", "MULTIPOLYGON", "sfg"])
+        .set_class([ords.dim.as_str(), "GEOMETRYCOLLECTION", "sfg"])
         .unwrap()
         .clone()
 }
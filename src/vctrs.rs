@@ -9,6 +9,9 @@
 //!
 //! Missing geometries are stored as an `extendr_api::NULL` object. Be sure to handle
 //! them accordingly.
+//!
+//! Use [`geoms_to_rsgeo()`] to create a vctr directly from a `Vec<Option<Geom>>`.
+use crate::{constructors::into_geom_robj, geometry_name, Geom};
 use extendr_api::prelude::*;
 use extendr_api::List;
 
@@ -17,6 +20,40 @@ pub fn as_rsgeo_vctr(mut x: List, class: &str) -> Robj {
     x.set_class(geom_class(class)).unwrap().clone().into()
 }
 
+/// Create an rsgeo vctr from geometries such as a `Vec<Option<Geom>>`.
+/// `None` values are stored as `NULL`.
+///
+/// The `rs_{TYPE}` class is inferred from the geometries e.g. `rs_POLYGON`.
+/// When geometry types are mixed, or every geometry is missing, the class
+/// is `rs_GEOMETRYCOLLECTION`.
+pub fn geoms_to_rsgeo(x: impl IntoIterator<Item = Option<Geom>>) -> Robj {
+    let mut cls: Option<&'static str> = None;
+    let mut mixed = false;
+
+    let res = x
+        .into_iter()
+        .map(|xi| match xi {
+            Some(geom) => {
+                let name = geometry_name(&geom.geom);
+                match cls {
+                    None => cls = Some(name),
+                    Some(prev) if prev != name => mixed = true,
+                    _ => (),
+                }
+                into_geom_robj(geom.geom)
+            }
+            None => ().into_robj(),
+        })
+        .collect::<List>();
+
+    let cls = match cls {
+        Some(cls) if !mixed => cls,
+        _ => "geometrycollection",
+    };
+
+    as_rsgeo_vctr(res, cls)
+}
+
 /// Create a `String` array of the vctrs class
 pub fn geom_class(cls: &str) -> [String; 4] {
     let cls = cls.to_uppercase();
@@ -0,0 +1,239 @@
+//! Convert between `Geom` and vectors from the wk package
+//!
+//! [wk](https://paleolimbot.github.io/wk/) provides lightweight geometry
+//! vector classes. The following are supported:
+//!
+//! - `wk_wkb`: a list of WKB raw vectors. Missing geometries are `NULL`.
+//! - `wk_wkt`: a character vector of WKT. Missing geometries are `NA`.
+//! - `wk_xy`: a record of `x` and `y` coordinates. These are always `Point`s.
+//! - `wk_rct`: a record of `xmin`, `ymin`, `xmax`, and `ymax`. These are always `Rect`s.
+//!
+//! The `crs` attribute of wk vectors is read into a [`Crs`]. An sf `crs` object
+//! is read as is whereas a character string is treated as the CRS input. When
+//! writing, the CRS input, or the WKT if there is no input, is written as a
+//! character string.
+//!
+//! `wk_xy` and `wk_rct` cannot represent missing geometries. Missing points are
+//! written with `NA` coordinates and are read as empty points. Rectangles with
+//! `NA`, infinite, or inverted bounds, such as wk's empty rectangle
+//! `rct(Inf, Inf, -Inf, -Inf)`, are read as `None`.
+use crate::{
+    constructors::as_list,
+    crs::Crs,
+    error::SfConversionError,
+    fromsf::sfc_to_geoms_crs,
+    geometry_name,
+    wkb::{geoms_to_wkb, wkb_to_geoms, ByteOrder},
+    wkt::{geoms_to_wkt, wkt_to_geoms},
+    Geom,
+};
+use extendr_api::prelude::*;
+use geo_types::{coord, Geometry, Point, Rect};
+use std::result::Result;
+
+/// Convert any supported wk vector, or an sfc object, into geometries and
+/// the vector's `Crs`. The conversion is chosen by the class of `x`.
+pub fn wk_to_geoms(x: Robj) -> Result<(Vec<Option<Geom>>, Crs), SfConversionError> {
+    if x.inherits("sfc") {
+        return sfc_to_geoms_crs(as_list(x)?);
+    }
+
+    let crs = wk_crs(&x);
+    let geoms = if x.inherits("wk_wkb") {
+        wkb_to_geoms(as_list(x)?)?
+    } else if x.inherits("wk_wkt") {
+        let strings = Strings::try_from(x.clone())
+            .map_err(|_| SfConversionError::bad_storage("character", &x))?;
+        wkt_to_geoms(strings)?
+    } else if x.inherits("wk_xy") {
+        wk_xy_to_geoms(as_list(x)?)?
+    } else if x.inherits("wk_rct") {
+        wk_rct_to_geoms(as_list(x)?)?
+    } else {
+        return Err(SfConversionError::MissingClass("wk_vctr"));
+    };
+
+    Ok((geoms, crs))
+}
+
+/// Create a `wk_wkb` vector. `None` values are `NULL`.
+pub fn geoms_to_wk_wkb(x: &[Option<Geom>], crs: &Crs) -> Robj {
+    let res = geoms_to_wkb(x, ByteOrder::LittleEndian);
+    wk_vctr(res, ["wk_wkb", "wk_vctr"], crs)
+}
+
+/// Create a `wk_wkt` vector. `None` values are `NA`.
+pub fn geoms_to_wk_wkt(x: &[Option<Geom>], crs: &Crs) -> Robj {
+    let res = geoms_to_wkt(x).into_robj();
+    wk_vctr(res, ["wk_wkt", "wk_vctr"], crs)
+}
+
+/// Create a `wk_xy` vector from `Point`s. `None` values and empty points have
+/// `NA` coordinates. Any other geometry type returns an error.
+pub fn geoms_to_wk_xy(x: &[Option<Geom>], crs: &Crs) -> Result<Robj, SfConversionError> {
+    let na = Rfloat::na().inner();
+    let mut xs = Vec::with_capacity(x.len());
+    let mut ys = Vec::with_capacity(x.len());
+
+    for xi in x {
+        match xi.as_ref().map(|g| &g.geom) {
+            Some(Geometry::Point(p)) if !(p.x().is_nan() && p.y().is_nan()) => {
+                xs.push(p.x());
+                ys.push(p.y());
+            }
+            Some(Geometry::Point(_)) | None => {
+                xs.push(na);
+                ys.push(na);
+            }
+            Some(geom) => {
+                return Err(SfConversionError::UnsupportedType(
+                    geometry_name(geom).to_string(),
+                ))
+            }
+        }
+    }
+
+    let res = List::from_names_and_values(["x", "y"], [Robj::from(xs), Robj::from(ys)])
+        .unwrap()
+        .into_robj();
+
+    Ok(wk_vctr(res, ["wk_xy", "wk_rcrd"], crs))
+}
+
+/// Create a `wk_rct` vector from `Rect`s. `None` values have `NA` bounds.
+/// Any other geometry type returns an error.
+pub fn geoms_to_wk_rct(x: &[Option<Geom>], crs: &Crs) -> Result<Robj, SfConversionError> {
+    let na = Rfloat::na().inner();
+    let mut bounds: [Vec<f64>; 4] = Default::default();
+
+    for xi in x {
+        let vals = match xi.as_ref().map(|g| &g.geom) {
+            Some(Geometry::Rect(r)) => [r.min().x, r.min().y, r.max().x, r.max().y],
+            None => [na; 4],
+            Some(geom) => {
+                return Err(SfConversionError::UnsupportedType(
+                    geometry_name(geom).to_string(),
+                ))
+            }
+        };
+
+        for (bound, val) in bounds.iter_mut().zip(vals) {
+            bound.push(val);
+        }
+    }
+
+    let res = List::from_names_and_values(["xmin", "ymin", "xmax", "ymax"], bounds.map(Robj::from))
+        .unwrap()
+        .into_robj();
+
+    Ok(wk_vctr(res, ["wk_rct", "wk_rcrd"], crs))
+}
+
+// Reads the `crs` attribute of a wk vector or sfc object
+fn wk_crs(x: &Robj) -> Crs {
+    let crs = match x.get_attrib("crs") {
+        Some(crs) if !crs.is_null() => crs,
+        _ => return Crs::default(),
+    };
+
+    if crs.inherits("crs") {
+        return Crs::try_from(crs).unwrap_or_default();
+    }
+
+    match crs.as_str() {
+        Some(input) => Crs::new(Some(input.to_string()), None),
+        None => Crs::default(),
+    }
+}
+
+// sets the wk class and `crs` attribute
+fn wk_vctr(mut x: Robj, cls: [&str; 2], crs: &Crs) -> Robj {
+    let crs = match (&crs.input, &crs.wkt) {
+        (Some(input), _) => Robj::from(input.as_str()),
+        (None, Some(wkt)) => Robj::from(wkt.as_str()),
+        (None, None) => Robj::from(NULL),
+    };
+
+    x.set_attrib("crs", crs)
+        .unwrap()
+        .set_class(cls)
+        .unwrap()
+        .clone()
+}
+
+// The named double vector fields of a wk record. wk_xyz, wk_xym, and wk_xyzm
+// records have additional fields. Every field must have the same length.
+fn record_fields(x: &List, names: &[&str]) -> Result<Vec<Doubles>, SfConversionError> {
+    if x.len() != names.len() {
+        return Err(SfConversionError::WrongDimensions {
+            expected: names.len(),
+            found: x.len(),
+        });
+    }
+
+    let fields = names
+        .iter()
+        .map(|name| record_field(x, name))
+        .collect::<Result<Vec<Doubles>, SfConversionError>>()?;
+
+    let n = fields.first().map_or(0, |f| f.len());
+    if let Some(field) = fields.iter().find(|f| f.len() != n) {
+        return Err(SfConversionError::LengthMismatch {
+            expected: n,
+            found: field.len(),
+        });
+    }
+
+    Ok(fields)
+}
+
+// a named double vector field of a wk record
+fn record_field(x: &List, name: &str) -> Result<Doubles, SfConversionError> {
+    let field = x
+        .iter()
+        .find(|(nm, _)| *nm == name)
+        .map(|(_, field)| field)
+        .ok_or_else(|| SfConversionError::MissingField(name.to_string()))?;
+
+    Doubles::try_from(field.clone()).map_err(|_| SfConversionError::bad_storage("double", &field))
+}
+
+fn wk_xy_to_geoms(x: List) -> Result<Vec<Option<Geom>>, SfConversionError> {
+    let fields = record_fields(&x, &["x", "y"])?;
+
+    let res = fields[0]
+        .iter()
+        .zip(fields[1].iter())
+        .map(|(x, y)| {
+            // NA coordinates are an empty point
+            let (x, y) = if x.is_na() && y.is_na() {
+                (f64::NAN, f64::NAN)
+            } else {
+                (x.inner(), y.inner())
+            };
+            Some(Geom::from(Point::new(x, y)))
+        })
+        .collect();
+
+    Ok(res)
+}
+
+// NA, infinite, and inverted bounds, as used by wk for empty rectangles, are `None`
+fn wk_rct_to_geoms(x: List) -> Result<Vec<Option<Geom>>, SfConversionError> {
+    let fields = record_fields(&x, &["xmin", "ymin", "xmax", "ymax"])?;
+
+    let res = (0..fields[0].len())
+        .map(|i| {
+            let [xmin, ymin, xmax, ymax] = [0, 1, 2, 3].map(|j| fields[j][i].inner());
+            let finite = [xmin, ymin, xmax, ymax].iter().all(|b| b.is_finite());
+            if !finite || xmin > xmax || ymin > ymax {
+                return None;
+            }
+
+            let rect = Rect::new(coord! { x: xmin, y: ymin }, coord! { x: xmax, y: ymax });
+            Some(Geom::from(rect))
+        })
+        .collect();
+
+    Ok(res)
+}
@@ -0,0 +1,559 @@
+//! Read and write Well-Known Binary (WKB)
+//!
+//! sf's `st_as_binary()` creates a list of raw vectors with class `WKB`.
+//! These can be converted to and from a `Vec<Option<Geom>>` without
+//! inspecting sfg class strings. Both little-endian and big-endian WKB are
+//! supported when reading; the byte order is chosen when writing.
+//!
+//! `POINT EMPTY` is written as a point with NaN ordinates. This is the same
+//! convention used by sf and GEOS. `Line`, `Rect`, and `Triangle` are written
+//! as a `LINESTRING` and `POLYGON` respectively.
+//!
+//! Only two dimensional WKB can be read. Geometries with Z or M ordinates
+//! return [`SfConversionError::WrongDimensions`].
+use crate::{
+    constructors::{polygon_from_rings, validate_ring},
+    error::SfConversionError,
+    Geom,
+};
+use extendr_api::prelude::*;
+use geo_types::{
+    Coord, Geometry, GeometryCollection, LineString, MultiLineString, MultiPoint, MultiPolygon,
+    Point, Polygon,
+};
+use std::result::Result;
+
+/// The byte order of WKB
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ByteOrder {
+    BigEndian,
+    #[default]
+    LittleEndian,
+}
+
+impl ByteOrder {
+    // the byte order flag which begins each WKB geometry
+    fn flag(&self) -> u8 {
+        match self {
+            ByteOrder::BigEndian => 0,
+            ByteOrder::LittleEndian => 1,
+        }
+    }
+
+    /// Determine the byte order from an R endian string `"big"` or `"little"`
+    pub fn from_endian(x: &str) -> Option<ByteOrder> {
+        match x {
+            "big" => Some(ByteOrder::BigEndian),
+            "little" => Some(ByteOrder::LittleEndian),
+            _ => None,
+        }
+    }
+}
+
+// WKB geometry type codes
+const WKB_POINT: u32 = 1;
+const WKB_LINESTRING: u32 = 2;
+const WKB_POLYGON: u32 = 3;
+const WKB_MULTIPOINT: u32 = 4;
+const WKB_MULTILINESTRING: u32 = 5;
+const WKB_MULTIPOLYGON: u32 = 6;
+const WKB_GEOMETRYCOLLECTION: u32 = 7;
+
+// EWKB flags set in the high bits of the geometry type code
+pub(crate) const EWKB_Z: u32 = 0x80000000;
+pub(crate) const EWKB_M: u32 = 0x40000000;
+pub(crate) const EWKB_SRID: u32 = 0x20000000;
+
+impl Geom {
+    /// Write the geometry as WKB
+    pub fn to_wkb(&self, byte_order: ByteOrder) -> Vec<u8> {
+        geometry_to_wkb(&self.geom, byte_order)
+    }
+}
+
+/// Write a `Geometry` as WKB
+pub fn geometry_to_wkb(x: &Geometry, byte_order: ByteOrder) -> Vec<u8> {
+    let mut writer = WkbWriter::new(byte_order);
+    writer.geometry(x);
+    writer.buf
+}
+
+/// Read a WKB geometry into a `Geom`
+pub fn wkb_to_geom(x: &[u8]) -> Result<Geom, SfConversionError> {
+    let mut reader = WkbReader::new(x);
+    let geom = reader.geometry()?;
+    reader.finish()?;
+    Ok(Geom::from(geom))
+}
+
+/// Convert a list of raw vectors, such as a `WKB` class object created by
+/// `sf::st_as_binary()`, into geometries. `NULL` elements are `None`.
+pub fn wkb_to_geoms(x: List) -> Result<Vec<Option<Geom>>, SfConversionError> {
+    x.into_iter()
+        .map(|(_, xi)| {
+            if xi.is_null() {
+                return Ok(None);
+            }
+
+            let bytes = xi
+                .as_raw_slice()
+                .ok_or_else(|| SfConversionError::bad_storage("raw", &xi))?;
+            wkb_to_geom(bytes).map(Some)
+        })
+        .collect()
+}
+
+/// Create a `WKB` class list of raw vectors. `None` values are `NULL`.
+pub fn geoms_to_wkb(x: &[Option<Geom>], byte_order: ByteOrder) -> Robj {
+    let res = List::from_values(x.iter().map(|xi| match xi {
+        Some(geom) => Raw::from_bytes(&geom.to_wkb(byte_order)).into_robj(),
+        None => ().into_robj(),
+    }));
+
+    res.into_robj().set_class(["WKB"]).unwrap().clone()
+}
+
+// WKB writing --------------------------------------------------------------
+
+pub(crate) struct WkbWriter {
+    byte_order: ByteOrder,
+    pub(crate) buf: Vec<u8>,
+}
+
+impl WkbWriter {
+    pub(crate) fn new(byte_order: ByteOrder) -> Self {
+        WkbWriter {
+            byte_order,
+            buf: Vec::new(),
+        }
+    }
+
+    pub(crate) fn u32(&mut self, x: u32) {
+        match self.byte_order {
+            ByteOrder::BigEndian => self.buf.extend_from_slice(&x.to_be_bytes()),
+            ByteOrder::LittleEndian => self.buf.extend_from_slice(&x.to_le_bytes()),
+        }
+    }
+
+    fn f64(&mut self, x: f64) {
+        match self.byte_order {
+            ByteOrder::BigEndian => self.buf.extend_from_slice(&x.to_be_bytes()),
+            ByteOrder::LittleEndian => self.buf.extend_from_slice(&x.to_le_bytes()),
+        }
+    }
+
+    pub(crate) fn header(&mut self, typ: u32) {
+        self.buf.push(self.byte_order.flag());
+        self.u32(typ);
+    }
+
+    fn coords(&mut self, x: &[Coord]) {
+        self.u32(x.len() as u32);
+        for crd in x {
+            self.f64(crd.x);
+            self.f64(crd.y);
+        }
+    }
+
+    fn point(&mut self, x: &Point) {
+        self.header(WKB_POINT);
+        self.f64(x.x());
+        self.f64(x.y());
+    }
+
+    fn linestring(&mut self, x: &LineString) {
+        self.header(WKB_LINESTRING);
+        self.coords(&x.0);
+    }
+
+    fn polygon(&mut self, x: &Polygon) {
+        self.header(WKB_POLYGON);
+        self.polygon_body(x);
+    }
+
+    // the rings of a polygon. An empty polygon has no rings.
+    pub(crate) fn polygon_body(&mut self, x: &Polygon) {
+        if x.exterior().0.is_empty() {
+            self.u32(0);
+            return;
+        }
+
+        self.u32(x.interiors().len() as u32 + 1);
+        self.coords(&x.exterior().0);
+        for interior in x.interiors() {
+            self.coords(&interior.0);
+        }
+    }
+
+    pub(crate) fn geometry(&mut self, x: &Geometry) {
+        self.geometry_with_header(x, |w, typ| w.header(typ))
+    }
+
+    // writes a geometry using `header` to write the header of the outer
+    // geometry. Nested geometries always use the plain WKB header.
+    pub(crate) fn geometry_with_header(
+        &mut self,
+        x: &Geometry,
+        header: impl FnOnce(&mut Self, u32),
+    ) {
+        match x {
+            Geometry::Point(x) => {
+                header(self, WKB_POINT);
+                self.f64(x.x());
+                self.f64(x.y());
+            }
+            Geometry::Line(x) => {
+                header(self, WKB_LINESTRING);
+                self.coords(&[x.start, x.end]);
+            }
+            Geometry::LineString(x) => {
+                header(self, WKB_LINESTRING);
+                self.coords(&x.0);
+            }
+            Geometry::Polygon(x) => {
+                header(self, WKB_POLYGON);
+                self.polygon_body(x);
+            }
+            Geometry::Rect(x) => {
+                header(self, WKB_POLYGON);
+                self.polygon_body(&x.to_polygon());
+            }
+            Geometry::Triangle(x) => {
+                header(self, WKB_POLYGON);
+                self.polygon_body(&x.to_polygon());
+            }
+            Geometry::MultiPoint(x) => {
+                header(self, WKB_MULTIPOINT);
+                self.u32(x.0.len() as u32);
+                x.iter().for_each(|p| self.point(p));
+            }
+            Geometry::MultiLineString(x) => {
+                header(self, WKB_MULTILINESTRING);
+                self.u32(x.0.len() as u32);
+                x.iter().for_each(|l| self.linestring(l));
+            }
+            Geometry::MultiPolygon(x) => {
+                header(self, WKB_MULTIPOLYGON);
+                self.u32(x.0.len() as u32);
+                x.iter().for_each(|p| self.polygon(p));
+            }
+            Geometry::GeometryCollection(x) => {
+                header(self, WKB_GEOMETRYCOLLECTION);
+                self.u32(x.0.len() as u32);
+                x.iter().for_each(|g| self.geometry(g));
+            }
+        }
+    }
+}
+
+// WKB reading --------------------------------------------------------------
+
+pub(crate) struct WkbReader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+    byte_order: ByteOrder,
+}
+
+impl<'a> WkbReader<'a> {
+    pub(crate) fn new(buf: &'a [u8]) -> Self {
+        WkbReader {
+            buf,
+            pos: 0,
+            byte_order: ByteOrder::LittleEndian,
+        }
+    }
+
+    fn take<const N: usize>(&mut self) -> Result<[u8; N], SfConversionError> {
+        let bytes = self
+            .buf
+            .get(self.pos..self.pos + N)
+            .ok_or_else(|| SfConversionError::Parse("unexpected end of WKB".into()))?;
+        self.pos += N;
+        Ok(bytes.try_into().unwrap())
+    }
+
+    pub(crate) fn u32(&mut self) -> Result<u32, SfConversionError> {
+        let bytes = self.take::<4>()?;
+        Ok(match self.byte_order {
+            ByteOrder::BigEndian => u32::from_be_bytes(bytes),
+            ByteOrder::LittleEndian => u32::from_le_bytes(bytes),
+        })
+    }
+
+    fn f64(&mut self) -> Result<f64, SfConversionError> {
+        let bytes = self.take::<8>()?;
+        Ok(match self.byte_order {
+            ByteOrder::BigEndian => f64::from_be_bytes(bytes),
+            ByteOrder::LittleEndian => f64::from_le_bytes(bytes),
+        })
+    }
+
+    pub(crate) fn finish(&self) -> Result<(), SfConversionError> {
+        if self.pos != self.buf.len() {
+            return Err(SfConversionError::Parse(format!(
+                "{} trailing bytes after WKB geometry",
+                self.buf.len() - self.pos
+            )));
+        }
+        Ok(())
+    }
+
+    // reads the byte order flag and the raw geometry type
+    pub(crate) fn header(&mut self) -> Result<u32, SfConversionError> {
+        self.byte_order = match self.take::<1>()?[0] {
+            0 => ByteOrder::BigEndian,
+            1 => ByteOrder::LittleEndian,
+            flag => {
+                return Err(SfConversionError::Parse(format!(
+                    "invalid byte order flag `{flag}`"
+                )))
+            }
+        };
+        self.u32()
+    }
+
+    // ISO WKB encodes Z, M, and ZM in the thousands of the type code
+    // whereas EWKB uses the high bits of the type code
+    pub(crate) fn xy_type(typ: u32) -> Result<u32, SfConversionError> {
+        let has_z = typ & EWKB_Z != 0;
+        let has_m = typ & EWKB_M != 0;
+        if has_z || has_m {
+            return Err(SfConversionError::WrongDimensions {
+                expected: 2,
+                found: 2 + has_z as usize + has_m as usize,
+            });
+        }
+
+        match typ / 1000 {
+            0 => Ok(typ),
+            1 | 2 => Err(SfConversionError::WrongDimensions {
+                expected: 2,
+                found: 3,
+            }),
+            3 => Err(SfConversionError::WrongDimensions {
+                expected: 2,
+                found: 4,
+            }),
+            _ => Err(SfConversionError::Parse(format!(
+                "invalid WKB geometry type `{typ}`"
+            ))),
+        }
+    }
+
+    pub(crate) fn geometry(&mut self) -> Result<Geometry, SfConversionError> {
+        let typ = Self::xy_type(self.header()?)?;
+        self.geometry_body(typ)
+    }
+
+    // reads a geometry of the given type after its header has been read
+    pub(crate) fn geometry_body(&mut self, typ: u32) -> Result<Geometry, SfConversionError> {
+        let geom: Geometry = match typ {
+            WKB_POINT => self.point_body()?.into(),
+            WKB_LINESTRING => LineString::new(self.coords()?).into(),
+            WKB_POLYGON => self.polygon_body()?.into(),
+            WKB_MULTIPOINT => MultiPoint::new(self.members(WKB_POINT, |r| r.point_body())?).into(),
+            WKB_MULTILINESTRING => MultiLineString::new(
+                self.members(WKB_LINESTRING, |r| Ok(LineString::new(r.coords()?)))?,
+            )
+            .into(),
+            WKB_MULTIPOLYGON => {
+                MultiPolygon::new(self.members(WKB_POLYGON, |r| r.polygon_body())?).into()
+            }
+            WKB_GEOMETRYCOLLECTION => {
+                let n = self.u32()?;
+                let geoms = (0..n)
+                    .map(|_| self.geometry())
+                    .collect::<Result<Vec<Geometry>, SfConversionError>>()?;
+                GeometryCollection::new_from(geoms).into()
+            }
+            _ => {
+                return Err(SfConversionError::UnsupportedType(format!(
+                    "WKB geometry type {typ}"
+                )))
+            }
+        };
+
+        Ok(geom)
+    }
+
+    // the members of a multi geometry each have their own header
+    // which must be of the expected type
+    fn members<T>(
+        &mut self,
+        expected: u32,
+        read_member: impl Fn(&mut Self) -> Result<T, SfConversionError>,
+    ) -> Result<Vec<T>, SfConversionError> {
+        let n = self.u32()?;
+        (0..n)
+            .map(|_| {
+                let typ = Self::xy_type(self.header()?)?;
+                if typ != expected {
+                    return Err(SfConversionError::Parse(format!(
+                        "expected WKB geometry type {expected}, found {typ}"
+                    )));
+                }
+                read_member(self)
+            })
+            .collect()
+    }
+
+    fn point_body(&mut self) -> Result<Point, SfConversionError> {
+        Ok(Point::new(self.f64()?, self.f64()?))
+    }
+
+    fn coords(&mut self) -> Result<Vec<Coord>, SfConversionError> {
+        let n = self.u32()? as usize;
+        // guard against allocating for a corrupt length
+        if n > (self.buf.len() - self.pos) / 16 {
+            return Err(SfConversionError::Parse("unexpected end of WKB".into()));
+        }
+
+        (0..n)
+            .map(|_| {
+                Ok(Coord {
+                    x: self.f64()?,
+                    y: self.f64()?,
+                })
+            })
+            .collect()
+    }
+
+    fn polygon_body(&mut self) -> Result<Polygon, SfConversionError> {
+        let n = self.u32()?;
+        let rings = (0..n)
+            .map(|_| {
+                let coords = self.coords()?;
+                validate_ring(&coords)?;
+                Ok(LineString::new(coords))
+            })
+            .collect::<Result<Vec<LineString>, SfConversionError>>()?;
+
+        Ok(polygon_from_rings(rings))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wkt::wkt_to_geom;
+
+    // writes WKT as WKB in both byte orders and reads it back
+    fn round_trip(x: &str) {
+        let geom = wkt_to_geom(x).unwrap();
+        for byte_order in [ByteOrder::LittleEndian, ByteOrder::BigEndian] {
+            let res = wkb_to_geom(&geom.to_wkb(byte_order)).unwrap();
+            assert_eq!(res.to_wkt(), x);
+        }
+    }
+
+    // a little-endian WKB header followed by `body`
+    fn wkb(typ: u32, body: &[u8]) -> Vec<u8> {
+        let mut res = vec![1];
+        res.extend_from_slice(&typ.to_le_bytes());
+        res.extend_from_slice(body);
+        res
+    }
+
+    fn coords(x: &[f64]) -> Vec<u8> {
+        x.iter().flat_map(|v| v.to_le_bytes()).collect()
+    }
+
+    #[test]
+    fn points_are_encoded_in_either_byte_order() {
+        let geom = wkt_to_geom("POINT (1 2)").unwrap();
+
+        let little = geom.to_wkb(ByteOrder::LittleEndian);
+        assert_eq!(little, wkb(WKB_POINT, &coords(&[1.0, 2.0])));
+
+        let mut big = vec![0, 0, 0, 0, 1];
+        big.extend_from_slice(&1_f64.to_be_bytes());
+        big.extend_from_slice(&2_f64.to_be_bytes());
+        assert_eq!(geom.to_wkb(ByteOrder::BigEndian), big);
+    }
+
+    #[test]
+    fn geometries_round_trip() {
+        round_trip("POINT (1 2)");
+        round_trip("LINESTRING (0 0, 1.5 -2)");
+        round_trip("POLYGON ((0 0, 1 0, 1 1, 0 0), (0.2 0.2, 0.8 0.2, 0.8 0.8, 0.2 0.2))");
+        round_trip("MULTIPOINT ((1 2), (3 4))");
+        round_trip("MULTILINESTRING ((0 0, 1 1), (2 2, 3 3))");
+        round_trip("MULTIPOLYGON (((0 0, 1 0, 1 1, 0 0)), ((2 2, 3 2, 3 3, 2 2)))");
+        round_trip("GEOMETRYCOLLECTION (POINT (1 2), GEOMETRYCOLLECTION (LINESTRING (0 0, 1 1)))");
+    }
+
+    #[test]
+    fn empties_round_trip() {
+        round_trip("POINT EMPTY");
+        round_trip("LINESTRING EMPTY");
+        round_trip("POLYGON EMPTY");
+        round_trip("MULTIPOINT ((1 2), EMPTY)");
+        round_trip("MULTILINESTRING EMPTY");
+        round_trip("MULTIPOLYGON (((0 0, 1 0, 1 1, 0 0)), EMPTY)");
+        round_trip("GEOMETRYCOLLECTION (POINT EMPTY, POLYGON EMPTY)");
+        round_trip("GEOMETRYCOLLECTION EMPTY");
+    }
+
+    #[test]
+    fn z_and_m_are_rejected() {
+        let point_z = coords(&[1.0, 2.0, 3.0]);
+
+        for typ in [1001, WKB_POINT | EWKB_Z, WKB_POINT | EWKB_M] {
+            assert_eq!(
+                wkb_to_geom(&wkb(typ, &point_z)).unwrap_err(),
+                SfConversionError::WrongDimensions {
+                    expected: 2,
+                    found: 3
+                }
+            );
+        }
+
+        assert_eq!(
+            wkb_to_geom(&wkb(3001, &coords(&[1.0, 2.0, 3.0, 4.0]))).unwrap_err(),
+            SfConversionError::WrongDimensions {
+                expected: 2,
+                found: 4
+            }
+        );
+    }
+
+    #[test]
+    fn malformed_wkb_is_an_error() {
+        let point = coords(&[1.0, 2.0]);
+
+        // empty, truncated, and trailing bytes
+        assert!(wkb_to_geom(&[]).is_err());
+        assert!(wkb_to_geom(&wkb(WKB_POINT, &point[..8])).is_err());
+
+        let mut trailing = wkb(WKB_POINT, &point);
+        trailing.push(0);
+        assert!(wkb_to_geom(&trailing).is_err());
+
+        // an invalid byte order flag
+        let mut bad_flag = wkb(WKB_POINT, &point);
+        bad_flag[0] = 2;
+        assert!(wkb_to_geom(&bad_flag).is_err());
+
+        // an unknown geometry type
+        assert!(matches!(
+            wkb_to_geom(&wkb(17, &point)),
+            Err(SfConversionError::UnsupportedType(_))
+        ));
+
+        // a corrupt coordinate count
+        assert!(wkb_to_geom(&wkb(WKB_LINESTRING, &u32::MAX.to_le_bytes())).is_err());
+
+        // a multipoint with a linestring member
+        let mut member = 1_u32.to_le_bytes().to_vec();
+        member.extend(wkb(WKB_LINESTRING, &0_u32.to_le_bytes()));
+        assert!(wkb_to_geom(&wkb(WKB_MULTIPOINT, &member)).is_err());
+
+        // an unclosed ring
+        let mut ring = [1_u32.to_le_bytes(), 4_u32.to_le_bytes()].concat();
+        ring.extend(coords(&[0.0, 0.0, 1.0, 0.0, 1.0, 1.0, 0.0, 1.0]));
+        assert!(matches!(
+            wkb_to_geom(&wkb(WKB_POLYGON, &ring)),
+            Err(SfConversionError::MalformedRing(_))
+        ));
+    }
+}
@@ -0,0 +1,573 @@
+//! Read and write Well-Known Text (WKT)
+//!
+//! WKT is written in the same form as sf e.g. `POINT (1 2)` and
+//! `POLYGON ((0 0, 1 0, 1 1, 0 0))`. Empty geometries are written as
+//! `<TYPE> EMPTY`. A `Point` with NaN x and y values is `POINT EMPTY`. Other
+//! non-finite ordinates are written as `NaN`, `inf`, and `-inf` and can be
+//! read back.
+//!
+//! `Line`, `Rect`, and `Triangle` have no WKT representation and are written
+//! as a `LINESTRING` and `POLYGON` respectively.
+//!
+//! Only two dimensional WKT can be parsed. Geometries with Z or M ordinates,
+//! whether flagged as `POINT Z` or `POINTZ`, return
+//! [`SfConversionError::WrongDimensions`].
+//!
+//! Missing geometries (`NULL` in an sfc or rsgeo vector) are `NA` in a
+//! character vector of WKT and `None` in a `Vec<Option<Geom>>`.
+use crate::{
+    constructors::{polygon_from_rings, validate_ring},
+    error::SfConversionError,
+    fromsf::sfc_to_geometry,
+    geometry_from_list, Geom,
+};
+use extendr_api::prelude::*;
+use geo_types::{
+    Coord, Geometry, GeometryCollection, LineString, MultiLineString, MultiPoint, MultiPolygon,
+    Point, Polygon,
+};
+use std::fmt::{self, Write};
+use std::result::Result;
+
+impl Geom {
+    /// Write the geometry as WKT
+    pub fn to_wkt(&self) -> String {
+        geometry_to_wkt(&self.geom)
+    }
+}
+
+impl fmt::Display for Geom {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_geometry(f, &self.geom)
+    }
+}
+
+/// Write a `Geometry` as WKT
+pub fn geometry_to_wkt(x: &Geometry) -> String {
+    let mut res = String::new();
+    write_geometry(&mut res, x).unwrap();
+    res
+}
+
+/// Parse a WKT string into a `Geom`
+pub fn wkt_to_geom(x: &str) -> Result<Geom, SfConversionError> {
+    let mut parser = WktParser::new(x)?;
+    let geom = parser.geometry()?;
+    parser.finish()?;
+    Ok(Geom::from(geom))
+}
+
+/// Parse a character vector of WKT. `NA` values are `None`.
+pub fn wkt_to_geoms(x: Strings) -> Result<Vec<Option<Geom>>, SfConversionError> {
+    x.iter()
+        .map(|xi| {
+            if xi.is_na() {
+                Ok(None)
+            } else {
+                wkt_to_geom(xi.as_str()).map(Some)
+            }
+        })
+        .collect()
+}
+
+/// Write geometries as a character vector of WKT. `None` values are `NA`.
+pub fn geoms_to_wkt(x: &[Option<Geom>]) -> Strings {
+    Strings::from_values(x.iter().map(|xi| match xi {
+        Some(geom) => Rstr::from(geom.to_wkt()),
+        None => Rstr::na(),
+    }))
+}
+
+/// Write an sfc object as a character vector of WKT. `NULL` geometries are `NA`.
+pub fn sfc_to_wkt(x: List) -> Result<Strings, SfConversionError> {
+    Ok(geometries_to_wkt(sfc_to_geometry(x)?))
+}
+
+/// Write an rsgeo vector as a character vector of WKT. `NULL` geometries are `NA`.
+pub fn rsgeo_to_wkt(x: List) -> Result<Strings, SfConversionError> {
+    Ok(geometries_to_wkt(geometry_from_list(x)?))
+}
+
+fn geometries_to_wkt(x: Vec<Option<Geometry>>) -> Strings {
+    Strings::from_values(x.iter().map(|xi| match xi {
+        Some(geom) => Rstr::from(geometry_to_wkt(geom)),
+        None => Rstr::na(),
+    }))
+}
+
+// WKT writing --------------------------------------------------------------
+
+fn write_geometry(f: &mut impl Write, x: &Geometry) -> fmt::Result {
+    match x {
+        Geometry::Point(x) => {
+            f.write_str("POINT ")?;
+            write_point(f, x)
+        }
+        Geometry::MultiPoint(x) => {
+            f.write_str("MULTIPOINT ")?;
+            write_list(f, &x.0, write_point)
+        }
+        Geometry::Line(x) => {
+            f.write_str("LINESTRING ")?;
+            write_list(f, &[x.start, x.end], write_coord)
+        }
+        Geometry::LineString(x) => {
+            f.write_str("LINESTRING ")?;
+            write_linestring(f, x)
+        }
+        Geometry::MultiLineString(x) => {
+            f.write_str("MULTILINESTRING ")?;
+            write_list(f, &x.0, write_linestring)
+        }
+        Geometry::Polygon(x) => {
+            f.write_str("POLYGON ")?;
+            write_polygon(f, x)
+        }
+        Geometry::MultiPolygon(x) => {
+            f.write_str("MULTIPOLYGON ")?;
+            write_list(f, &x.0, write_polygon)
+        }
+        Geometry::Rect(x) => {
+            f.write_str("POLYGON ")?;
+            write_polygon(f, &x.to_polygon())
+        }
+        Geometry::Triangle(x) => {
+            f.write_str("POLYGON ")?;
+            write_polygon(f, &x.to_polygon())
+        }
+        Geometry::GeometryCollection(x) => {
+            f.write_str("GEOMETRYCOLLECTION ")?;
+            write_list(f, &x.0, write_geometry)
+        }
+    }
+}
+
+// writes `EMPTY` for an empty slice otherwise a parenthesized, comma separated list
+fn write_list<W: Write, T>(
+    f: &mut W,
+    x: &[T],
+    write_item: fn(&mut W, &T) -> fmt::Result,
+) -> fmt::Result {
+    if x.is_empty() {
+        return f.write_str("EMPTY");
+    }
+
+    f.write_char('(')?;
+    for (i, xi) in x.iter().enumerate() {
+        if i > 0 {
+            f.write_str(", ")?;
+        }
+        write_item(f, xi)?;
+    }
+    f.write_char(')')
+}
+
+fn write_coord(f: &mut impl Write, x: &Coord) -> fmt::Result {
+    write!(f, "{} {}", x.x, x.y)
+}
+
+fn write_point(f: &mut impl Write, x: &Point) -> fmt::Result {
+    if x.x().is_nan() && x.y().is_nan() {
+        f.write_str("EMPTY")
+    } else {
+        f.write_char('(')?;
+        write_coord(f, &x.0)?;
+        f.write_char(')')
+    }
+}
+
+fn write_linestring(f: &mut impl Write, x: &LineString) -> fmt::Result {
+    write_list(f, &x.0, write_coord)
+}
+
+fn write_polygon(f: &mut impl Write, x: &Polygon) -> fmt::Result {
+    if x.exterior().0.is_empty() {
+        return f.write_str("EMPTY");
+    }
+
+    f.write_char('(')?;
+    write_linestring(f, x.exterior())?;
+    for interior in x.interiors() {
+        f.write_str(", ")?;
+        write_linestring(f, interior)?;
+    }
+    f.write_char(')')
+}
+
+// WKT parsing --------------------------------------------------------------
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Word(String),
+    Number(f64),
+    LParen,
+    RParen,
+    Comma,
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Word(w) => f.write_str(w),
+            Token::Number(n) => write!(f, "{n}"),
+            Token::LParen => f.write_char('('),
+            Token::RParen => f.write_char(')'),
+            Token::Comma => f.write_char(','),
+        }
+    }
+}
+
+fn tokenize(x: &str) -> Result<Vec<Token>, SfConversionError> {
+    let mut tokens = Vec::new();
+    let mut chars = x.char_indices().peekable();
+
+    while let Some((start, ch)) = chars.next() {
+        match ch {
+            '(' => tokens.push(Token::LParen),
+            ')' => tokens.push(Token::RParen),
+            ',' => tokens.push(Token::Comma),
+            c if c.is_whitespace() => {}
+            // `NaN`, `inf`, and `infinity` are numbers
+            c if c.is_ascii_alphabetic() => {
+                let mut end = start + 1;
+                while let Some((i, _)) = chars.next_if(|(_, c)| c.is_ascii_alphabetic()) {
+                    end = i + 1;
+                }
+                let word = &x[start..end];
+                match word.parse::<f64>() {
+                    Ok(num) => tokens.push(Token::Number(num)),
+                    Err(_) => tokens.push(Token::Word(word.to_uppercase())),
+                }
+            }
+            // letters are included for exponents and signed values such as `-inf`
+            c if c.is_ascii_digit() || matches!(c, '-' | '+' | '.') => {
+                let mut end = start + 1;
+                while let Some((i, _)) = chars
+                    .next_if(|(_, c)| c.is_ascii_alphanumeric() || matches!(*c, '-' | '+' | '.'))
+                {
+                    end = i + 1;
+                }
+                let num = x[start..end].parse::<f64>().map_err(|_| {
+                    SfConversionError::Parse(format!("invalid number `{}`", &x[start..end]))
+                })?;
+                tokens.push(Token::Number(num));
+            }
+            c => {
+                return Err(SfConversionError::Parse(format!(
+                    "unexpected character `{c}`"
+                )))
+            }
+        }
+    }
+
+    Ok(tokens)
+}
+
+const WKT_TYPES: [&str; 7] = [
+    "POINT",
+    "LINESTRING",
+    "POLYGON",
+    "MULTIPOINT",
+    "MULTILINESTRING",
+    "MULTIPOLYGON",
+    "GEOMETRYCOLLECTION",
+];
+
+// the number of ordinates of a dimension word e.g. `3` for `Z`
+fn n_ordinates(dim: &str) -> usize {
+    match dim {
+        "Z" | "M" => 3,
+        "ZM" => 4,
+        _ => 2,
+    }
+}
+
+// splits a dimension suffix from a geometry type e.g. `POINTZ` is `("POINT", 3)`
+fn split_dimension(typ: &str) -> (&str, usize) {
+    for dim in ["ZM", "Z", "M"] {
+        if let Some(base) = typ.strip_suffix(dim) {
+            if WKT_TYPES.contains(&base) {
+                return (base, n_ordinates(dim));
+            }
+        }
+    }
+
+    (typ, 2)
+}
+
+struct WktParser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl WktParser {
+    fn new(x: &str) -> Result<Self, SfConversionError> {
+        Ok(WktParser {
+            tokens: tokenize(x)?,
+            pos: 0,
+        })
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Result<Token, SfConversionError> {
+        let tkn = self
+            .tokens
+            .get(self.pos)
+            .cloned()
+            .ok_or_else(|| SfConversionError::Parse("unexpected end of WKT".into()))?;
+        self.pos += 1;
+        Ok(tkn)
+    }
+
+    fn expect(&mut self, expected: Token) -> Result<(), SfConversionError> {
+        let tkn = self.next()?;
+        if tkn != expected {
+            return Err(SfConversionError::Parse(format!(
+                "expected `{expected}`, found `{tkn}`"
+            )));
+        }
+        Ok(())
+    }
+
+    // consumes the next token if it matches
+    fn consume(&mut self, x: &Token) -> bool {
+        if self.peek() == Some(x) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn consume_empty(&mut self) -> bool {
+        self.consume(&Token::Word(String::from("EMPTY")))
+    }
+
+    fn finish(&self) -> Result<(), SfConversionError> {
+        match self.peek() {
+            Some(tkn) => Err(SfConversionError::Parse(format!(
+                "unexpected `{tkn}` after geometry"
+            ))),
+            None => Ok(()),
+        }
+    }
+
+    fn geometry(&mut self) -> Result<Geometry, SfConversionError> {
+        let typ = match self.next()? {
+            Token::Word(w) => w,
+            tkn => {
+                return Err(SfConversionError::Parse(format!(
+                    "expected a geometry type, found `{tkn}`"
+                )))
+            }
+        };
+
+        // only XY geometries are supported. The dimension may be a separate
+        // word as in `POINT Z` or a suffix as in `POINTZ`.
+        let (typ, suffix) = split_dimension(&typ);
+        let found = match self.peek() {
+            Some(Token::Word(dim)) if suffix == 2 => n_ordinates(dim),
+            _ => suffix,
+        };
+
+        if found != 2 {
+            return Err(SfConversionError::WrongDimensions { expected: 2, found });
+        }
+
+        let geom: Geometry = match typ {
+            "POINT" => self.point()?.into(),
+            "LINESTRING" => self.linestring()?.into(),
+            "POLYGON" => self.polygon()?.into(),
+            "MULTIPOINT" => MultiPoint::new(self.list(Self::multipoint_member)?).into(),
+            "MULTILINESTRING" => MultiLineString::new(self.list(Self::linestring)?).into(),
+            "MULTIPOLYGON" => MultiPolygon::new(self.list(Self::polygon)?).into(),
+            "GEOMETRYCOLLECTION" => GeometryCollection::new_from(self.list(Self::geometry)?).into(),
+            _ => return Err(SfConversionError::UnsupportedType(typ.to_string())),
+        };
+
+        Ok(geom)
+    }
+
+    // `EMPTY` or a parenthesized, comma separated list
+    fn list<T>(
+        &mut self,
+        parse_item: fn(&mut Self) -> Result<T, SfConversionError>,
+    ) -> Result<Vec<T>, SfConversionError> {
+        if self.consume_empty() {
+            return Ok(Vec::new());
+        }
+
+        self.expect(Token::LParen)?;
+        let mut items = vec![parse_item(self)?];
+        while self.consume(&Token::Comma) {
+            items.push(parse_item(self)?);
+        }
+        self.expect(Token::RParen)?;
+
+        Ok(items)
+    }
+
+    fn coord(&mut self) -> Result<Coord, SfConversionError> {
+        let mut ords = Vec::with_capacity(2);
+        while let Some(Token::Number(n)) = self.peek() {
+            ords.push(*n);
+            self.pos += 1;
+        }
+
+        if ords.len() != 2 {
+            return Err(SfConversionError::WrongDimensions {
+                expected: 2,
+                found: ords.len(),
+            });
+        }
+
+        Ok(Coord {
+            x: ords[0],
+            y: ords[1],
+        })
+    }
+
+    fn point(&mut self) -> Result<Point, SfConversionError> {
+        if self.consume_empty() {
+            return Ok(Point::new(f64::NAN, f64::NAN));
+        }
+
+        self.expect(Token::LParen)?;
+        let crd = self.coord()?;
+        self.expect(Token::RParen)?;
+        Ok(Point::from(crd))
+    }
+
+    // multipoint members may or may not be wrapped in parentheses
+    fn multipoint_member(&mut self) -> Result<Point, SfConversionError> {
+        match self.peek() {
+            Some(Token::Number(_)) => Ok(Point::from(self.coord()?)),
+            _ => self.point(),
+        }
+    }
+
+    fn linestring(&mut self) -> Result<LineString, SfConversionError> {
+        Ok(LineString::new(self.list(Self::coord)?))
+    }
+
+    fn ring(&mut self) -> Result<LineString, SfConversionError> {
+        let coords = self.list(Self::coord)?;
+        validate_ring(&coords)?;
+        Ok(LineString::new(coords))
+    }
+
+    fn polygon(&mut self) -> Result<Polygon, SfConversionError> {
+        let rings = self.list(Self::ring)?;
+
+        Ok(polygon_from_rings(rings))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // parses and rewrites WKT which must be unchanged
+    fn round_trip(x: &str) {
+        let geom = wkt_to_geom(x).unwrap();
+        assert_eq!(geom.to_wkt(), x);
+    }
+
+    #[test]
+    fn geometries_round_trip() {
+        round_trip("POINT (1 2)");
+        round_trip("LINESTRING (0 0, 1.5 -2)");
+        round_trip("POLYGON ((0 0, 1 0, 1 1, 0 0), (0.2 0.2, 0.8 0.2, 0.8 0.8, 0.2 0.2))");
+        round_trip("MULTIPOINT ((1 2), (3 4))");
+        round_trip("MULTILINESTRING ((0 0, 1 1), (2 2, 3 3))");
+        round_trip("MULTIPOLYGON (((0 0, 1 0, 1 1, 0 0)), ((2 2, 3 2, 3 3, 2 2)))");
+        round_trip("GEOMETRYCOLLECTION (POINT (1 2), LINESTRING (0 0, 1 1))");
+    }
+
+    #[test]
+    fn empties_round_trip() {
+        round_trip("POINT EMPTY");
+        round_trip("LINESTRING EMPTY");
+        round_trip("POLYGON EMPTY");
+        round_trip("MULTIPOINT EMPTY");
+        round_trip("MULTIPOINT ((1 2), EMPTY)");
+        round_trip("MULTILINESTRING ((0 0, 1 1), EMPTY)");
+        round_trip("MULTIPOLYGON (((0 0, 1 0, 1 1, 0 0)), EMPTY)");
+        round_trip("GEOMETRYCOLLECTION EMPTY");
+        round_trip("GEOMETRYCOLLECTION (POINT EMPTY, POLYGON EMPTY)");
+
+        let geom = wkt_to_geom("POINT EMPTY").unwrap();
+        assert!(geom.is_empty());
+    }
+
+    #[test]
+    fn non_finite_ordinates_round_trip() {
+        round_trip("LINESTRING (NaN NaN, 1 inf, -inf 2)");
+        round_trip("POINT (NaN 2)");
+    }
+
+    #[test]
+    fn input_is_normalised() {
+        let geom = wkt_to_geom("multipoint(1 2,3 4)").unwrap();
+        assert_eq!(geom.to_wkt(), "MULTIPOINT ((1 2), (3 4))");
+
+        let geom = wkt_to_geom("  POINT(1e2 -2.5E-1)  ").unwrap();
+        assert_eq!(geom.to_wkt(), "POINT (100 -0.25)");
+    }
+
+    #[test]
+    fn z_and_m_are_rejected() {
+        for x in [
+            "POINT Z (1 2 3)",
+            "POINTZ (1 2 3)",
+            "LINESTRING M (0 0 1, 1 1 1)",
+            "POINT ZM (1 2 3 4)",
+            "POINTZM (1 2 3 4)",
+        ] {
+            assert!(
+                matches!(
+                    wkt_to_geom(x),
+                    Err(SfConversionError::WrongDimensions { expected: 2, .. })
+                ),
+                "{x}"
+            );
+        }
+
+        assert!(matches!(
+            wkt_to_geom("POINT (1 2 3)"),
+            Err(SfConversionError::WrongDimensions {
+                expected: 2,
+                found: 3
+            })
+        ));
+    }
+
+    #[test]
+    fn malformed_wkt_is_an_error() {
+        let parse_errors = [
+            "",
+            "POINT (1 2",
+            "POINT (1 2) 3",
+            "POINT (1 2) @",
+            "POINT (1.2.3 4)",
+            "LINESTRING (0 0 1 1)",
+            "(1 2)",
+        ];
+
+        for x in parse_errors {
+            assert!(wkt_to_geom(x).is_err(), "{x}");
+        }
+
+        assert!(matches!(
+            wkt_to_geom("POLYGON ((0 0, 1 0, 1 1))"),
+            Err(SfConversionError::MalformedRing(_))
+        ));
+        assert!(matches!(
+            wkt_to_geom("CIRCULARSTRING (0 0, 1 1, 2 0)"),
+            Err(SfConversionError::UnsupportedType(_))
+        ));
+    }
+}
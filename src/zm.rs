@@ -0,0 +1,270 @@
+//! Support for sfg objects with Z and M dimensions
+//!
+//! geo-types geometries are strictly two dimensional whereas sf supports
+//! `"XYZ"`, `"XYM"`, and `"XYZM"` geometries. `GeomZM` carries the additional
+//! ordinates alongside a `Geom` so that they can be written back to sf with
+//! the correct dimension class.
+//!
+//! Z and M values are stored as flat vectors in the order in which coordinates
+//! appear in the sfg object. This is the same order that coordinates are visited
+//! by geo's [`CoordsIter`](https://docs.rs/geo/latest/geo/algorithm/coords_iter/trait.CoordsIter.html).
+//!
+//! Dropping the Z and M dimensions is always an explicit choice made by
+//! calling [`GeomZM::drop_zm()`].
+use crate::{error::SfConversionError, Geom};
+use extendr_api::prelude::*;
+use geo::CoordsIter;
+use geo_types::{coord, Coord};
+
+use std::result::Result;
+
+/// The coordinate dimension of an sfg object. This is the first
+/// element of the sfg class e.g. `c("XYZ", "POINT", "sfg")`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Dim {
+    XY,
+    XYZ,
+    XYM,
+    XYZM,
+}
+
+impl Dim {
+    /// Parse a dimension from an sfg class string such as `"XYZ"`
+    pub fn from_class(cls: &str) -> Option<Dim> {
+        match cls {
+            "XY" => Some(Dim::XY),
+            "XYZ" => Some(Dim::XYZ),
+            "XYM" => Some(Dim::XYM),
+            "XYZM" => Some(Dim::XYZM),
+            _ => None,
+        }
+    }
+
+    /// Determine the dimension of an sfg object from its class attribute
+    pub fn from_sfg(x: &Robj) -> Option<Dim> {
+        Dim::from_class(x.class()?.next()?)
+    }
+
+    /// The sfg class string of the dimension
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Dim::XY => "XY",
+            Dim::XYZ => "XYZ",
+            Dim::XYM => "XYM",
+            Dim::XYZM => "XYZM",
+        }
+    }
+
+    /// The number of ordinates (matrix columns) each coordinate has
+    pub fn n_dims(&self) -> usize {
+        match self {
+            Dim::XY => 2,
+            Dim::XYZ | Dim::XYM => 3,
+            Dim::XYZM => 4,
+        }
+    }
+
+    pub fn has_z(&self) -> bool {
+        matches!(self, Dim::XYZ | Dim::XYZM)
+    }
+
+    pub fn has_m(&self) -> bool {
+        matches!(self, Dim::XYM | Dim::XYZM)
+    }
+
+    // column index of the M ordinate
+    fn m_col(&self) -> usize {
+        if self.has_z() {
+            3
+        } else {
+            2
+        }
+    }
+}
+
+/// A `Geom` with its Z and M ordinates. When the dimension does not have a
+/// Z or M component the corresponding vector is empty.
+#[derive(Debug, Clone)]
+pub struct GeomZM {
+    /// the two dimensional geometry
+    pub geom: Geom,
+    /// the coordinate dimension of the geometry
+    pub dim: Dim,
+    /// Z values for each coordinate in `CoordsIter` order
+    pub z: Vec<f64>,
+    /// M values for each coordinate in `CoordsIter` order
+    pub m: Vec<f64>,
+}
+
+impl GeomZM {
+    /// Fallibly create a `GeomZM`. The number of Z and M values must match
+    /// the number of coordinates in the geometry.
+    pub fn new(geom: Geom, dim: Dim, z: Vec<f64>, m: Vec<f64>) -> Result<Self, SfConversionError> {
+        let n = geom.geom.coords_count();
+        let n_z = if dim.has_z() { n } else { 0 };
+        let n_m = if dim.has_m() { n } else { 0 };
+
+        if z.len() != n_z {
+            return Err(SfConversionError::LengthMismatch {
+                expected: n_z,
+                found: z.len(),
+            });
+        }
+
+        if m.len() != n_m {
+            return Err(SfConversionError::LengthMismatch {
+                expected: n_m,
+                found: m.len(),
+            });
+        }
+
+        Ok(GeomZM { geom, dim, z, m })
+    }
+
+    /// Explicitly discard the Z and M ordinates returning the `Geom`
+    pub fn drop_zm(self) -> Geom {
+        self.geom
+    }
+}
+
+impl From<Geom> for GeomZM {
+    fn from(geom: Geom) -> Self {
+        GeomZM {
+            geom,
+            dim: Dim::XY,
+            z: Vec::new(),
+            m: Vec::new(),
+        }
+    }
+}
+
+// Collects Z and M ordinates while reading sfg coordinate matrices
+pub(crate) struct OrdinateReader {
+    pub(crate) dim: Dim,
+    pub(crate) z: Vec<f64>,
+    pub(crate) m: Vec<f64>,
+}
+
+impl OrdinateReader {
+    pub(crate) fn new(dim: Dim) -> Self {
+        OrdinateReader {
+            dim,
+            z: Vec::new(),
+            m: Vec::new(),
+        }
+    }
+
+    // read a single coordinate from a row of ordinates
+    fn push(&mut self, row: &[f64]) -> Coord {
+        if self.dim.has_z() {
+            self.z.push(row[2]);
+        }
+
+        if self.dim.has_m() {
+            self.m.push(row[self.dim.m_col()]);
+        }
+
+        coord! { x: row[0], y: row[1] }
+    }
+
+    pub(crate) fn point(&mut self, x: Doubles) -> Result<Coord, SfConversionError> {
+        let n = self.dim.n_dims();
+        if x.len() != n {
+            return Err(SfConversionError::WrongDimensions {
+                expected: n,
+                found: x.len(),
+            });
+        }
+
+        let row = x.iter().map(|xi| xi.inner()).collect::<Vec<f64>>();
+        Ok(self.push(&row))
+    }
+
+    pub(crate) fn coords(&mut self, x: RMatrix<f64>) -> Result<Vec<Coord>, SfConversionError> {
+        let nrow = x.nrows();
+        let ncol = x.ncols();
+        let n = self.dim.n_dims();
+
+        if ncol != n {
+            return Err(SfConversionError::WrongDimensions {
+                expected: n,
+                found: ncol,
+            });
+        }
+
+        let mut coords: Vec<Coord> = Vec::with_capacity(nrow);
+        let mut row = [0.0; 4];
+
+        for i in 0..nrow {
+            for (j, rj) in row.iter_mut().enumerate().take(ncol) {
+                *rj = x[[i, j]];
+            }
+            coords.push(self.push(&row[..ncol]));
+        }
+
+        Ok(coords)
+    }
+}
+
+// Consumes Z and M ordinates while writing sfg coordinate matrices
+pub(crate) struct OrdinateWriter<'a> {
+    pub(crate) dim: Dim,
+    z: &'a [f64],
+    m: &'a [f64],
+    pos: usize,
+}
+
+impl<'a> OrdinateWriter<'a> {
+    pub(crate) fn xy() -> Self {
+        OrdinateWriter {
+            dim: Dim::XY,
+            z: &[],
+            m: &[],
+            pos: 0,
+        }
+    }
+
+    pub(crate) fn new(dim: Dim, z: &'a [f64], m: &'a [f64]) -> Self {
+        OrdinateWriter { dim, z, m, pos: 0 }
+    }
+
+    // the full row of ordinates for the next coordinate.
+    // Missing Z or M values are written as NA
+    pub(crate) fn row(&mut self, crd: Coord) -> Vec<f64> {
+        let na = Rfloat::na().inner();
+        let mut row = Vec::with_capacity(self.dim.n_dims());
+        row.push(crd.x);
+        row.push(crd.y);
+
+        if self.dim.has_z() {
+            row.push(self.z.get(self.pos).copied().unwrap_or(na));
+        }
+
+        if self.dim.has_m() {
+            row.push(self.m.get(self.pos).copied().unwrap_or(na));
+        }
+
+        self.pos += 1;
+        row
+    }
+
+    pub(crate) fn matrix(&mut self, coords: impl Iterator<Item = Coord>) -> RMatrix<f64> {
+        let rows = coords.map(|crd| self.row(crd)).collect::<Vec<Vec<f64>>>();
+        self.rows_matrix(rows)
+    }
+
+    // The rows of a closed ring from the corners of a `Rect` or `Triangle` in
+    // `CoordsIter` order, the order their Z and M values are stored in. The
+    // first row, including its Z and M values, is repeated to close the ring.
+    pub(crate) fn closed_ring(&mut self, corners: impl Iterator<Item = Coord>) -> Vec<Vec<f64>> {
+        let mut rows = corners.map(|crd| self.row(crd)).collect::<Vec<Vec<f64>>>();
+        if let Some(first) = rows.first().cloned() {
+            rows.push(first);
+        }
+        rows
+    }
+
+    pub(crate) fn rows_matrix(&self, rows: Vec<Vec<f64>>) -> RMatrix<f64> {
+        RMatrix::new_matrix(rows.len(), self.dim.n_dims(), |r, c| rows[r][c])
+    }
+}